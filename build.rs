@@ -0,0 +1,4 @@
+fn main() {
+    // Rebuild when a migration is added, since they are embedded
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS forecasts (
+    lat FLOAT8 NOT NULL,
+    lng FLOAT8 NOT NULL,
+    variables TEXT NOT NULL,
+    response JSONB NOT NULL,
+    fetched_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (lat, lng, variables)
+);
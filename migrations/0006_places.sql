@@ -0,0 +1,10 @@
+-- The geocoder's record of each city, so it needn't be asked again
+ALTER TABLE cities
+    ADD COLUMN place_id BIGINT UNIQUE,
+    ADD COLUMN canonical_name TEXT,
+    ADD COLUMN country TEXT,
+    ADD COLUMN country_code TEXT,
+    ADD COLUMN admin1 TEXT,
+    ADD COLUMN timezone TEXT,
+    ADD COLUMN elevation FLOAT8,
+    ADD COLUMN population BIGINT;
@@ -0,0 +1,7 @@
+-- The spellings a city was looked up by, normalized, so each maps to one row
+CREATE TABLE city_aliases (
+    alias TEXT PRIMARY KEY,
+    city_id INTEGER NOT NULL REFERENCES cities (id) ON DELETE CASCADE
+);
+
+CREATE INDEX city_aliases_city_id_idx ON city_aliases (city_id);
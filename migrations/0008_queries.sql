@@ -0,0 +1,15 @@
+-- Every weather request, for the usage statistics
+CREATE TABLE queries (
+    id BIGSERIAL PRIMARY KEY,
+    city_id INTEGER REFERENCES cities (id) ON DELETE SET NULL,
+    requested_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- Whether the forecast was served without asking the upstream, NULL if
+    -- no forecast was served
+    cache_hit BOOLEAN,
+    upstream_error BOOLEAN NOT NULL,
+    status SMALLINT NOT NULL,
+    latency_ms FLOAT8 NOT NULL,
+    client TEXT NOT NULL
+);
+
+CREATE INDEX queries_requested_at_idx ON queries (requested_at);
@@ -0,0 +1,175 @@
+//! The JSON API under `/api/v1` and content negotiation for the pages.
+
+use {
+    crate::{
+        api_key::{MaybeScoped, Scoped, StatsRead, WeatherRead},
+        error::{Error, ErrorBody},
+        provider::{Current, Daily, Hourly, Place},
+        stats::StatsQuery,
+        units::Units,
+        App, City, View, WeatherQuery,
+    },
+    axum::{
+        extract::{rejection::QueryRejection, ConnectInfo, FromRequestParts, Query, State},
+        http::{header, request::Parts, HeaderMap, HeaderValue, Request},
+        middleware::Next,
+        response::{IntoResponse, Response},
+        Json,
+    },
+    serde::{Deserialize, Serialize},
+    serde_json::json,
+    std::{borrow::Cow, convert::Infallible, net::SocketAddr},
+};
+
+/// The response format preferred by the client.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Html,
+    Json,
+}
+
+impl Format {
+    /// Picks the format from the `Accept` header, preferring HTML on ties.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut html = 0.;
+        let mut json = 0.;
+        let accept = headers.get_all(header::ACCEPT).iter();
+        for range in accept.filter_map(|value| value.to_str().ok()) {
+            for item in range.split(',') {
+                let mut params = item.split(';').map(str::trim);
+                let media = params.next().unwrap_or_default();
+                let quality = params
+                    .find_map(|param| param.strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.);
+
+                match media {
+                    "text/html" | "text/*" | "*/*" => html = f64::max(html, quality),
+                    "application/json" | "application/*" => json = f64::max(json, quality),
+                    _ => {}
+                }
+            }
+        }
+
+        if json > html {
+            Self::Json
+        } else {
+            Self::Html
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<S> FromRequestParts<S> for Format
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_headers(&parts.headers))
+    }
+}
+
+/// Rewrites error responses as JSON for API routes and for clients that
+/// prefer JSON.
+pub async fn json_errors<B>(req: Request<B>, next: Next<B>) -> Response {
+    let json = req.uri().path().starts_with("/api/")
+        || Format::from_headers(req.headers()) == Format::Json;
+
+    let res = next.run(req).await;
+    if !json {
+        return res;
+    }
+
+    let Some(ErrorBody { code, message }) = res.extensions().get().cloned() else {
+        return res;
+    };
+
+    let (mut parts, _) = res.into_parts();
+    let body = json!({
+        "error": {
+            "status": parts.status.as_u16(),
+            "code": code,
+            "message": message,
+        },
+    });
+
+    let content_type = HeaderValue::from_static("application/json");
+    parts.headers.insert(header::CONTENT_TYPE, content_type);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Json(body).into_response().into_body())
+}
+
+#[derive(Serialize)]
+pub struct WeatherBody<'a> {
+    pub city: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub place: Option<&'a Place>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub view: View,
+    pub units: Units,
+    /// The time zone of the times, which are local to the location.
+    pub timezone: Option<&'a str>,
+    pub utc_offset_seconds: i32,
+    /// When the forecast was fetched, if it's past its TTL.
+    pub stale: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hourly: Option<&'a Hourly>,
+    /// The current conditions, along with the hourly forecast.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<&'a Current>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily: Option<Cow<'a, Daily>>,
+}
+
+pub async fn weather(
+    caller: MaybeScoped<WeatherRead>,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    query: Result<Query<WeatherQuery>, QueryRejection>,
+    state: State<App>,
+) -> Result<Response, Error> {
+    crate::weather(Format::Json, caller, addr, headers, query, state).await
+}
+
+pub async fn stats(
+    caller: Scoped<StatsRead>,
+    query: Result<Query<StatsQuery>, QueryRejection>,
+    state: State<App>,
+) -> Result<Response, Error> {
+    crate::stats(Format::Json, caller, query, state).await
+}
+
+#[derive(Deserialize)]
+pub struct CitiesQuery {
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct CitiesBody {
+    cities: Vec<City>,
+}
+
+pub async fn cities(
+    _: Scoped<StatsRead>,
+    query: Result<Query<CitiesQuery>, QueryRejection>,
+    State(app): State<App>,
+) -> Result<Json<CitiesBody>, Error> {
+    const MAX_LIMIT: i64 = 1000;
+
+    let Query(query) = query?;
+    let limit = query.limit.unwrap_or(100);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        let message = format!("limit must be between 1 and {MAX_LIMIT}");
+        return Err(Error::BadRequest(message));
+    }
+
+    let cities = sqlx::query_as("SELECT name, lat, lng FROM cities ORDER BY name LIMIT $1")
+        .bind(limit)
+        .fetch_all(&app.pool)
+        .await?;
+
+    Ok(Json(CitiesBody { cities }))
+}
@@ -0,0 +1,325 @@
+//! API keys for programmatic access, limited to the scopes they were issued
+//! with and sent as `Authorization: Bearer <key>`.
+
+use {
+    crate::{
+        error::Error,
+        session,
+        user::{Admin, RequireRole, User, Viewer},
+        App,
+    },
+    askama_axum::Template,
+    axum::{
+        extract::{rejection::FormRejection, FromRequestParts, Path, State},
+        headers::{authorization::Bearer, Authorization},
+        http::{header, request::Parts},
+        response::{IntoResponse, Redirect, Response},
+        Form, TypedHeader,
+    },
+    rand::{distributions::Alphanumeric, Rng},
+    serde::Serialize,
+    sha2::{Digest, Sha256},
+    sqlx::{
+        postgres::{PgHasArrayType, PgTypeInfo},
+        FromRow, PgPool,
+    },
+    std::{collections::HashMap, fmt, marker::PhantomData},
+};
+
+/// Marks the keys so they are easy to recognize, e.g. by secret scanners.
+const KEY_PREFIX: &str = "fk_";
+const KEY_LEN: usize = 40;
+
+/// How many characters of a key are kept in clear to tell the keys apart.
+const SHOWN_LEN: usize = KEY_PREFIX.len() + 8;
+
+const MAX_NAME_LEN: usize = 64;
+
+/// The longest lifetime a key may be issued with, about ten years.
+const MAX_EXPIRES_IN_DAYS: i32 = 3650;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[sqlx(type_name = "text")]
+pub enum Scope {
+    #[serde(rename = "weather:read")]
+    #[sqlx(rename = "weather:read")]
+    WeatherRead,
+    #[serde(rename = "stats:read")]
+    #[sqlx(rename = "stats:read")]
+    StatsRead,
+}
+
+impl Scope {
+    pub const ALL: [Self; 2] = [Self::WeatherRead, Self::StatsRead];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::WeatherRead => "weather:read",
+            Self::StatsRead => "stats:read",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl PgHasArrayType for Scope {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_text")
+    }
+}
+
+/// The scope a route requires, as the parameter of [`Scoped`].
+pub trait Required {
+    const SCOPE: Scope;
+}
+
+pub struct WeatherRead;
+
+impl Required for WeatherRead {
+    const SCOPE: Scope = Scope::WeatherRead;
+}
+
+pub struct StatsRead;
+
+impl Required for StatsRead {
+    const SCOPE: Scope = Scope::StatsRead;
+}
+
+/// A valid API key that made a request.
+#[derive(Clone, FromRow, Serialize)]
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl ApiKey {
+    /// Looks up an unexpired key and records its use.
+    pub async fn authenticate(pool: &PgPool, key: &str) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as(
+            "UPDATE api_keys SET last_used_at = now() \
+            WHERE key_hash = $1 AND (expires_at IS NULL OR expires_at > now()) \
+            RETURNING id, name, scopes",
+        )
+        .bind(hash(key))
+        .fetch_optional(pool)
+        .await
+    }
+}
+
+/// Who made an authenticated request.
+pub enum Caller {
+    User(User),
+    Key(ApiKey),
+}
+
+/// A caller allowed to use a route that requires the scope `S`: either an
+/// API key granted that scope or a signed in user.
+pub struct Scoped<S>(pub Caller, pub PhantomData<S>);
+
+#[async_trait::async_trait]
+impl<S> FromRequestParts<App> for Scoped<S>
+where
+    S: Required,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, app: &App) -> Result<Self, Self::Rejection> {
+        let bearer = TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, app).await;
+        let Ok(TypedHeader(Authorization(bearer))) = bearer else {
+            let RequireRole(user, _) =
+                RequireRole::<Viewer>::from_request_parts(parts, app).await?;
+            return Ok(Self(Caller::User(user), PhantomData));
+        };
+
+        // The rate limiter may have checked the key already
+        let key = match parts.extensions.get::<ApiKey>() {
+            Some(key) => key.clone(),
+            None => ApiKey::authenticate(&app.pool, bearer.token())
+                .await?
+                .ok_or(Error::Unauthorized)?,
+        };
+
+        if !key.scopes.contains(&S::SCOPE) {
+            let message = format!("the API key lacks the {} scope", S::SCOPE);
+            return Err(Error::Forbidden(message));
+        }
+
+        Ok(Self(Caller::Key(key), PhantomData))
+    }
+}
+
+/// Like [`Scoped`] for routes open to anonymous callers, so credentials
+/// are only checked when given.
+pub struct MaybeScoped<S>(pub Option<Caller>, pub PhantomData<S>);
+
+#[async_trait::async_trait]
+impl<S> FromRequestParts<App> for MaybeScoped<S>
+where
+    S: Required,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, app: &App) -> Result<Self, Self::Rejection> {
+        if !parts.headers.contains_key(header::AUTHORIZATION) {
+            return Ok(Self(None, PhantomData));
+        }
+
+        let Scoped(caller, _) = Scoped::<S>::from_request_parts(parts, app).await?;
+        Ok(Self(Some(caller), PhantomData))
+    }
+}
+
+fn hash(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// A key as listed on the admin page.
+#[derive(FromRow)]
+struct Listed {
+    id: i32,
+    name: String,
+    prefix: String,
+    scopes: Vec<Scope>,
+    created_at: String,
+    expires_at: Option<String>,
+    last_used_at: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "keys.html")]
+struct KeysView {
+    csrf: String,
+    keys: Vec<Listed>,
+    scopes: [Scope; 2],
+    /// A key that was just issued, shown only this once.
+    issued: Option<String>,
+}
+
+impl KeysView {
+    async fn new(app: &App, csrf: String, issued: Option<String>) -> Result<Self, Error> {
+        const FORMAT: &str = "'YYYY-MM-DD HH24:MI \"UTC\"'";
+
+        let keys = sqlx::query_as(&format!(
+            "SELECT id, name, prefix, scopes, \
+                to_char(created_at AT TIME ZONE 'UTC', {FORMAT}) AS created_at, \
+                to_char(expires_at AT TIME ZONE 'UTC', {FORMAT}) AS expires_at, \
+                to_char(last_used_at AT TIME ZONE 'UTC', {FORMAT}) AS last_used_at \
+            FROM api_keys ORDER BY id",
+        ))
+        .fetch_all(&app.pool)
+        .await?;
+
+        Ok(Self {
+            csrf,
+            keys,
+            scopes: Scope::ALL,
+            issued,
+        })
+    }
+}
+
+/// Keys are only managed from a login session, since the forms need the
+/// CSRF token of the session.
+fn admin_session(user: &User) -> Result<String, Error> {
+    match &user.csrf {
+        Some(csrf) => Ok(csrf.clone()),
+        None => Err(Error::Forbidden(
+            "sign in at /login to manage API keys".to_owned(),
+        )),
+    }
+}
+
+pub async fn list(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(app): State<App>,
+) -> Result<Response, Error> {
+    let csrf = admin_session(&user)?;
+    Ok(KeysView::new(&app, csrf, None).await?.into_response())
+}
+
+/// Issues a key from the form fields `name`, `expires_in_days` (optional),
+/// `csrf` and one checkbox per granted scope, named after the scope.
+pub async fn issue(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(app): State<App>,
+    form: Result<Form<HashMap<String, String>>, FormRejection>,
+) -> Result<Response, Error> {
+    let Form(form) = form?;
+    let csrf = admin_session(&user)?;
+    session::check_csrf(Some(&csrf), form.get("csrf").map_or("", String::as_str))?;
+
+    let name = form.get("name").map_or("", |name| name.trim());
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        let message = format!("the name must be 1 to {MAX_NAME_LEN} bytes long");
+        return Err(Error::BadRequest(message));
+    }
+
+    let scopes: Vec<_> = Scope::ALL
+        .into_iter()
+        .filter(|scope| form.contains_key(scope.name()))
+        .collect();
+
+    if scopes.is_empty() {
+        return Err(Error::BadRequest("grant at least one scope".to_owned()));
+    }
+
+    let expires_in_days = match form.get("expires_in_days").map(|days| days.trim()) {
+        None | Some("") => None,
+        Some(days) => match days.parse::<i32>() {
+            Ok(days) if (1..=MAX_EXPIRES_IN_DAYS).contains(&days) => Some(days),
+            _ => {
+                let message =
+                    format!("expires_in_days must be from 1 to {MAX_EXPIRES_IN_DAYS} days");
+                return Err(Error::BadRequest(message));
+            }
+        },
+    };
+
+    let key: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(KEY_LEN)
+        .map(char::from)
+        .collect();
+
+    let key = format!("{KEY_PREFIX}{key}");
+    sqlx::query(
+        "INSERT INTO api_keys (name, prefix, key_hash, scopes, created_by, expires_at) \
+        VALUES ($1, $2, $3, $4, $5, now() + make_interval(days => $6))",
+    )
+    .bind(name)
+    .bind(&key[..SHOWN_LEN])
+    .bind(hash(&key))
+    .bind(scopes)
+    .bind(user.id)
+    .bind(expires_in_days)
+    .execute(&app.pool)
+    .await?;
+
+    Ok(KeysView::new(&app, csrf, Some(key)).await?.into_response())
+}
+
+pub async fn revoke(
+    RequireRole(user, _): RequireRole<Admin>,
+    Path(id): Path<i32>,
+    State(app): State<App>,
+    form: Result<Form<HashMap<String, String>>, FormRejection>,
+) -> Result<Response, Error> {
+    let Form(form) = form?;
+    let csrf = admin_session(&user)?;
+    session::check_csrf(Some(&csrf), form.get("csrf").map_or("", String::as_str))?;
+
+    sqlx::query("DELETE FROM api_keys WHERE id = $1")
+        .bind(id)
+        .execute(&app.pool)
+        .await?;
+
+    Ok(Redirect::to("/admin/keys").into_response())
+}
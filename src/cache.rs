@@ -0,0 +1,218 @@
+use {
+    crate::{
+        config,
+        location::{Location, Resolution},
+        provider::{WeatherRequest, WeatherResponse},
+        Error, Weather,
+    },
+    moka::{future::Cache, Expiry},
+    sqlx::{types::Json, PgPool},
+    std::{future::Future, sync::Arc, time::Duration, time::Instant},
+};
+
+/// The cache key of a forecast request.
+///
+/// Coordinates are rounded to two decimal places (about a kilometer), so
+/// nearby lookups share a forecast. The variables are sorted so their
+/// order in the query doesn't matter, and followed by the time zone the
+/// times are given in, since nearby places may be in different zones.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Key {
+    lat: i32,
+    lng: i32,
+    variables: String,
+    /// Whether the current conditions are requested, which expire sooner.
+    current: bool,
+}
+
+impl Key {
+    pub fn new(req: &WeatherRequest) -> Self {
+        let round = |deg: f64| (deg * 100.).round() as i32;
+
+        let mut variables: Vec<_> = req.hourly.iter().map(|var| var.name()).collect();
+        variables.sort_unstable();
+        if req.daily {
+            variables.push("daily");
+        }
+
+        if req.current {
+            variables.push("current");
+        }
+
+        let timezone = format!("timezone={}", req.timezone.as_deref().unwrap_or("auto"));
+        variables.push(&timezone);
+        Self {
+            lat: round(req.ll.lat),
+            lng: round(req.ll.lng),
+            variables: variables.join(","),
+            current: req.current,
+        }
+    }
+
+    /// How long the forecast stays fresh.
+    pub fn ttl(&self, config: &config::Cache) -> Duration {
+        if self.current {
+            config.current_ttl()
+        } else {
+            config.forecast_ttl()
+        }
+    }
+
+    fn lat(&self) -> f64 {
+        f64::from(self.lat) / 100.
+    }
+
+    fn lng(&self) -> f64 {
+        f64::from(self.lng) / 100.
+    }
+}
+
+/// In-process caches in front of the database and the upstream.
+///
+/// Concurrent lookups of the same key are coalesced, so only one of them
+/// runs and the rest wait for its result.
+#[derive(Clone)]
+pub struct Memory {
+    cities: Cache<String, Resolution>,
+    places: Cache<i64, Location>,
+    forecasts: Cache<Key, Weather>,
+}
+
+impl Memory {
+    pub fn new(config: &config::Cache) -> Self {
+        let cities = Cache::builder()
+            .max_capacity(config.memory_capacity)
+            .time_to_live(config.memory_ttl())
+            .build();
+
+        let places = Cache::builder()
+            .max_capacity(config.memory_capacity)
+            .time_to_live(config.memory_ttl())
+            .build();
+
+        let forecasts = Cache::builder()
+            .max_capacity(config.memory_capacity)
+            .expire_after(ForecastExpiry {
+                memory_ttl: config.memory_ttl(),
+                forecast_ttl: config.forecast_ttl(),
+                current_ttl: config.current_ttl(),
+            })
+            .build();
+
+        Self {
+            cities,
+            places,
+            forecasts,
+        }
+    }
+
+    pub async fn city<F>(&self, name: &str, init: F) -> Result<Resolution, Error>
+    where
+        F: Future<Output = Result<Resolution, Error>>,
+    {
+        self.cities
+            .try_get_with_by_ref(name, init)
+            .await
+            .map_err(Arc::unwrap_or_clone)
+    }
+
+    pub async fn place<F>(&self, id: i64, init: F) -> Result<Location, Error>
+    where
+        F: Future<Output = Result<Location, Error>>,
+    {
+        self.places
+            .try_get_with(id, init)
+            .await
+            .map_err(Arc::unwrap_or_clone)
+    }
+
+    pub async fn weather<F>(&self, key: Key, init: F) -> Result<Weather, Error>
+    where
+        F: Future<Output = Result<Weather, Error>>,
+    {
+        self.forecasts
+            .try_get_with(key, init)
+            .await
+            .map_err(Arc::unwrap_or_clone)
+    }
+}
+
+/// Keeps fresh forecasts for what's left of their TTL and drops stale
+/// ones right away, so they are only shared with the requests that were
+/// waiting for them.
+struct ForecastExpiry {
+    memory_ttl: Duration,
+    forecast_ttl: Duration,
+    current_ttl: Duration,
+}
+
+impl Expiry<Key, Weather> for ForecastExpiry {
+    fn expire_after_create(&self, key: &Key, weather: &Weather, _: Instant) -> Option<Duration> {
+        if weather.stale.is_some() {
+            return Some(Duration::ZERO);
+        }
+
+        let ttl = if key.current {
+            self.current_ttl
+        } else {
+            self.forecast_ttl
+        };
+
+        Some(self.memory_ttl.min(ttl.saturating_sub(weather.age)))
+    }
+}
+
+/// A forecast stored in the `forecasts` table.
+pub struct Cached {
+    pub response: WeatherResponse,
+    /// Whether the forecast is younger than the TTL.
+    pub fresh: bool,
+    /// How long ago the forecast was fetched.
+    pub age: Duration,
+    pub fetched_at: String,
+}
+
+pub async fn load(pool: &PgPool, key: &Key, ttl: Duration) -> Result<Option<Cached>, sqlx::Error> {
+    let row: Option<(Json<WeatherResponse>, bool, f64, String)> = sqlx::query_as(
+        "SELECT
+            response,
+            now() - fetched_at < make_interval(secs => $4),
+            extract(epoch FROM now() - fetched_at)::FLOAT8,
+            to_char(fetched_at AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI \"UTC\"')
+        FROM forecasts
+        WHERE lat = $1 AND lng = $2 AND variables = $3",
+    )
+    .bind(key.lat())
+    .bind(key.lng())
+    .bind(&key.variables)
+    .bind(ttl.as_secs_f64())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|(Json(response), fresh, age, fetched_at)| Cached {
+        response,
+        fresh,
+        age: Duration::try_from_secs_f64(age).unwrap_or_default(),
+        fetched_at,
+    }))
+}
+
+pub async fn store(
+    pool: &PgPool,
+    key: &Key,
+    response: &WeatherResponse,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO forecasts (lat, lng, variables, response) VALUES ($1, $2, $3, $4)
+        ON CONFLICT (lat, lng, variables)
+        DO UPDATE SET response = EXCLUDED.response, fetched_at = now()",
+    )
+    .bind(key.lat())
+    .bind(key.lng())
+    .bind(&key.variables)
+    .bind(Json(response))
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,232 @@
+//! Charts of the hourly forecast, rendered as SVG so the pages need no
+//! scripts.
+
+use {
+    crate::{
+        provider::Hourly,
+        units::{self, Units},
+    },
+    std::fmt::{self, Write},
+};
+
+const WIDTH: f64 = 720.;
+const HEIGHT: f64 = 240.;
+const LEFT: f64 = 10.;
+const RIGHT: f64 = 10.;
+const TOP: f64 = 24.;
+const BOTTOM: f64 = 24.;
+
+/// The share of the plot the tallest precipitation bar takes up.
+const PRECIPITATION_HEIGHT: f64 = 1. / 3.;
+
+/// The precipitation in mm that fills the bars' height, unless there is
+/// more.
+const MIN_PRECIPITATION_SCALE: f64 = 1.;
+
+/// Renders the temperature as a line and the precipitation as bars, with
+/// the day boundaries and the lowest and highest temperature marked.
+///
+/// The values are expected in `units` already.
+pub fn render(hourly: &Hourly, units: Units) -> String {
+    let mut svg = String::new();
+    Chart::new(hourly, units)
+        .write(&mut svg)
+        .expect("writing to a string doesn't fail");
+
+    svg
+}
+
+struct Chart<'a> {
+    hourly: &'a Hourly,
+    units: Units,
+    /// The temperature at the bottom and the top of the plot.
+    range: (f64, f64),
+}
+
+impl<'a> Chart<'a> {
+    fn new(hourly: &'a Hourly, units: Units) -> Self {
+        let temperatures = hourly.temperature_2m.iter().flatten().copied();
+        let range = temperatures.fold(None, |range, t| match range {
+            Some((min, max)) => Some((f64::min(min, t), f64::max(max, t))),
+            None => Some((t, t)),
+        });
+
+        // Leave room for the annotations above and below the line
+        let range = match range {
+            Some((min, max)) => {
+                let pad = f64::max((max - min) * 0.15, 1.);
+                (min - pad, max + pad)
+            }
+            None => (0., 1.),
+        };
+
+        Self {
+            hourly,
+            units,
+            range,
+        }
+    }
+
+    fn plot_width() -> f64 {
+        WIDTH - LEFT - RIGHT
+    }
+
+    fn plot_height() -> f64 {
+        HEIGHT - TOP - BOTTOM
+    }
+
+    /// The left edge of the hour `n`.
+    fn x(&self, n: usize) -> f64 {
+        let hours = self.hourly.time.len().max(1) as f64;
+        LEFT + Self::plot_width() * n as f64 / hours
+    }
+
+    /// The middle of the hour `n`.
+    fn x_mid(&self, n: usize) -> f64 {
+        (self.x(n) + self.x(n + 1)) / 2.
+    }
+
+    fn y(&self, temperature: f64) -> f64 {
+        let (bottom, top) = self.range;
+        TOP + Self::plot_height() * (top - temperature) / (top - bottom)
+    }
+
+    fn write(&self, svg: &mut String) -> fmt::Result {
+        write!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{WIDTH}\" height=\"{HEIGHT}\" \
+            viewBox=\"0 0 {WIDTH} {HEIGHT}\" role=\"img\" font-family=\"sans-serif\" \
+            font-size=\"11\"><title>Hourly temperature and precipitation</title>",
+        )?;
+
+        if self.hourly.time.is_empty() {
+            let (x, y) = (WIDTH / 2., HEIGHT / 2.);
+            write!(
+                svg,
+                "<text x=\"{x}\" y=\"{y}\" text-anchor=\"middle\">No forecast</text>"
+            )?;
+        } else {
+            self.write_days(svg)?;
+            self.write_precipitation(svg)?;
+            self.write_temperature(svg)?;
+        }
+
+        svg.push_str("</svg>");
+        Ok(())
+    }
+
+    /// Separates the days with dashed lines and labels them with the date.
+    fn write_days(&self, svg: &mut String) -> fmt::Result {
+        let bottom = HEIGHT - BOTTOM;
+        let mut date = None;
+        for (n, time) in self.hourly.time.iter().enumerate() {
+            let day = time.date();
+            if date == Some(day) {
+                continue;
+            }
+
+            let x = self.x(n);
+            if n > 0 {
+                write!(
+                    svg,
+                    "<line x1=\"{x:.1}\" y1=\"{TOP}\" x2=\"{x:.1}\" y2=\"{bottom}\" \
+                    stroke=\"#999\" stroke-dasharray=\"4 3\"/>",
+                )?;
+            }
+
+            write!(
+                svg,
+                "<text x=\"{:.1}\" y=\"{}\" fill=\"#555\">{}</text>",
+                x + 3.,
+                HEIGHT - 8.,
+                day,
+            )?;
+
+            date = Some(day);
+        }
+
+        Ok(())
+    }
+
+    fn write_precipitation(&self, svg: &mut String) -> fmt::Result {
+        let precipitation = &self.hourly.precipitation;
+        let scale = precipitation.iter().flatten().copied().fold(
+            self.units.convert_precipitation(MIN_PRECIPITATION_SCALE),
+            f64::max,
+        );
+
+        let unit = self.units.precipitation();
+
+        let bottom = HEIGHT - BOTTOM;
+        for (n, amount) in precipitation.iter().enumerate() {
+            let Some(amount) = amount.filter(|amount| *amount > 0.) else {
+                continue;
+            };
+
+            let width = (self.x(n + 1) - self.x(n)) * 0.8;
+            let height = Self::plot_height() * PRECIPITATION_HEIGHT * amount / scale;
+            write!(
+                svg,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{width:.1}\" height=\"{height:.1}\" \
+                fill=\"#4a90d9\" fill-opacity=\"0.6\"><title>{}</title></rect>",
+                self.x_mid(n) - width / 2.,
+                bottom - height,
+                units::with_unit(amount, unit),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Draws the line, broken where values are missing, and marks its
+    /// lowest and highest points.
+    fn write_temperature(&self, svg: &mut String) -> fmt::Result {
+        let temperatures = &self.hourly.temperature_2m;
+        let mut path = String::new();
+        let mut pen_down = false;
+        for (n, t) in temperatures.iter().enumerate() {
+            match t {
+                Some(t) => {
+                    let command = if pen_down { 'L' } else { 'M' };
+                    write!(path, "{command}{:.1},{:.1}", self.x_mid(n), self.y(*t))?;
+                    pen_down = true;
+                }
+                None => pen_down = false,
+            }
+        }
+
+        if path.is_empty() {
+            return Ok(());
+        }
+
+        write!(
+            svg,
+            "<path d=\"{path}\" fill=\"none\" stroke=\"#d9534f\" stroke-width=\"2\"/>",
+        )?;
+
+        let points = temperatures
+            .iter()
+            .enumerate()
+            .filter_map(|(n, t)| Some((n, (*t)?)));
+
+        // The first of equal values is marked
+        let min = points.clone().reduce(|a, b| if b.1 < a.1 { b } else { a });
+        let max = points.reduce(|a, b| if b.1 > a.1 { b } else { a });
+        for (label, point, dy) in [("min", min, 14.), ("max", max, -8.)] {
+            let Some((n, t)) = point else {
+                continue;
+            };
+
+            let (x, y) = (self.x_mid(n), self.y(t));
+            write!(
+                svg,
+                "<circle cx=\"{x:.1}\" cy=\"{y:.1}\" r=\"3\" fill=\"#d9534f\"/>\
+                <text x=\"{x:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{label} {}</text>",
+                y + dy,
+                units::with_unit(t, self.units.temperature()),
+            )?;
+        }
+
+        Ok(())
+    }
+}
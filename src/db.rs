@@ -0,0 +1,90 @@
+use {
+    sqlx::{migrate::Migrator, PgPool},
+    std::fmt,
+};
+
+/// The migrations in `migrations/`, embedded into the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Applies the pending migrations.
+pub async fn migrate(pool: &PgPool) -> Result<(), Error> {
+    MIGRATOR.run(pool).await.map_err(Error::Migrate)
+}
+
+/// Checks that the database schema matches the embedded migrations.
+pub async fn check(pool: &PgPool) -> Result<(), Error> {
+    let exists: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(pool)
+        .await?;
+
+    let applied: Vec<i64> = if exists {
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success ORDER BY version")
+            .fetch_all(pool)
+            .await?
+    } else {
+        vec![]
+    };
+
+    let current = applied.last().copied();
+    let expected = MIGRATOR.iter().map(|migration| migration.version).max();
+    if applied
+        .iter()
+        .any(|version| !MIGRATOR.version_exists(*version))
+    {
+        return Err(Error::Newer { current });
+    }
+
+    if MIGRATOR
+        .iter()
+        .any(|migration| !applied.contains(&migration.version))
+    {
+        return Err(Error::Outdated { current, expected });
+    }
+
+    Ok(())
+}
+
+pub enum Error {
+    Database(sqlx::Error),
+    Migrate(sqlx::migrate::MigrateError),
+    /// Some migrations haven't been applied yet.
+    Outdated {
+        current: Option<i64>,
+        expected: Option<i64>,
+    },
+    /// The database was migrated by a newer version of the server.
+    Newer {
+        current: Option<i64>,
+    },
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(v: sqlx::Error) -> Self {
+        Self::Database(v)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let version = |version: &Option<i64>| match version {
+            Some(version) => format!("version {version}"),
+            None => "no version".to_owned(),
+        };
+
+        match self {
+            Self::Database(err) => write!(f, "{err}"),
+            Self::Migrate(err) => write!(f, "migration failed: {err}"),
+            Self::Outdated { current, expected } => write!(
+                f,
+                "the schema is at {}, but {} is required, run `forecast migrate` first",
+                version(current),
+                version(expected),
+            ),
+            Self::Newer { current } => write!(
+                f,
+                "the schema is at {}, which this server doesn't know about, upgrade it",
+                version(current),
+            ),
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use {
+    crate::provider,
+    axum::{
+        extract::rejection::{FormRejection, QueryRejection},
+        http::{header, HeaderValue, StatusCode},
+        response::{IntoResponse, Response},
+    },
+    std::{sync::Arc, time::Duration},
+};
+
+#[derive(Clone)]
+pub enum Error {
+    BadRequest(String),
+    NoResultsFound,
+    Upstream(provider::Error),
+    Unauthorized,
+    /// Sends a browser to the login page at the given URI.
+    LoginRequired(String),
+    Forbidden(String),
+    /// The client is over its rate limit and may retry after the duration.
+    RateLimited(Duration),
+    Database(Arc<sqlx::Error>),
+}
+
+impl Error {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::NoResultsFound => StatusCode::NOT_FOUND,
+            Self::Upstream(provider::Error::Timeout(_)) => StatusCode::GATEWAY_TIMEOUT,
+            Self::Upstream(provider::Error::CircuitOpen(_) | provider::Error::Throttled(_)) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            Self::Upstream(_) => StatusCode::BAD_GATEWAY,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::LoginRequired(_) => StatusCode::SEE_OTHER,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// A stable, machine readable name of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::BadRequest(_) => "bad_request",
+            Self::NoResultsFound | Self::Upstream(provider::Error::NoMatch) => "no_results_found",
+            Self::Upstream(provider::Error::Timeout(_)) => "upstream_timeout",
+            Self::Upstream(provider::Error::Status(..)) => "upstream_status",
+            Self::Upstream(provider::Error::Payload(..)) => "upstream_payload",
+            Self::Upstream(provider::Error::Unavailable(..)) => "upstream_unavailable",
+            Self::Upstream(provider::Error::CircuitOpen(_)) => "upstream_circuit_open",
+            Self::Upstream(provider::Error::Throttled(_)) => "upstream_throttled",
+            Self::Unauthorized => "unauthorized",
+            Self::LoginRequired(_) => "login_required",
+            Self::Forbidden(_) => "forbidden",
+            Self::RateLimited(_) => "rate_limited",
+            Self::Database(_) => "internal",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Self::BadRequest(message) => message.clone(),
+            Self::NoResultsFound => "no results found".to_owned(),
+            Self::Upstream(err) => err.to_string(),
+            Self::Unauthorized => "unauthorized".to_owned(),
+            Self::LoginRequired(_) => "login required".to_owned(),
+            Self::Forbidden(message) => message.clone(),
+            Self::RateLimited(retry_after) => format!(
+                "too many requests, retry in {} seconds",
+                retry_after_secs(*retry_after),
+            ),
+            Self::Database(_) => "internal server error".to_owned(),
+        }
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(v: sqlx::Error) -> Self {
+        Self::Database(Arc::new(v))
+    }
+}
+
+impl From<provider::Error> for Error {
+    fn from(v: provider::Error) -> Self {
+        match v {
+            provider::Error::NoMatch => Self::NoResultsFound,
+            err => Self::Upstream(err),
+        }
+    }
+}
+
+impl From<QueryRejection> for Error {
+    fn from(v: QueryRejection) -> Self {
+        Self::BadRequest(v.body_text())
+    }
+}
+
+impl From<FormRejection> for Error {
+    fn from(v: FormRejection) -> Self {
+        Self::BadRequest(v.body_text())
+    }
+}
+
+/// The code and message of an error response, kept in its extensions so
+/// the body can be rewritten as JSON for clients that asked for it.
+#[derive(Clone)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        const AUTH_SCHEME_VALUE: &str = "Basic realm=\"Please enter your credentials\"";
+
+        match &self {
+            Self::Upstream(err) => eprintln!("upstream error: {err}"),
+            Self::Database(err) => eprintln!("database error: {err}"),
+            _ => {}
+        }
+
+        let message = self.message();
+        let mut res = (self.status(), message.clone()).into_response();
+        match &self {
+            Self::Unauthorized => {
+                let value = HeaderValue::from_static(AUTH_SCHEME_VALUE);
+                res.headers_mut().insert(header::WWW_AUTHENTICATE, value);
+            }
+            Self::RateLimited(retry_after) => {
+                let value = HeaderValue::from(retry_after_secs(*retry_after));
+                res.headers_mut().insert(header::RETRY_AFTER, value);
+            }
+            Self::LoginRequired(login) => {
+                if let Ok(value) = HeaderValue::from_str(login) {
+                    res.headers_mut().insert(header::LOCATION, value);
+                }
+            }
+            _ => {}
+        }
+
+        res.extensions_mut().insert(ErrorBody {
+            code: self.code(),
+            message,
+        });
+
+        res
+    }
+}
+
+/// Rounds up, since retrying any earlier would be rejected again.
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)
+}
@@ -0,0 +1,347 @@
+//! Resolving the place a forecast is requested for.
+
+use {
+    crate::{
+        api::Format,
+        error::Error,
+        provider::{LatLong, Place},
+        units::Units,
+        App, View, WeatherQuery,
+    },
+    askama_axum::Template,
+    axum::{
+        http::StatusCode,
+        response::{IntoResponse, Response},
+        Json,
+    },
+    serde::Serialize,
+    sqlx::PgPool,
+    std::sync::Arc,
+    unicode_normalization::{char::is_combining_mark, UnicodeNormalization},
+};
+
+/// How a [`WeatherQuery`] names the location.
+pub enum Target<'a> {
+    City(&'a str),
+    /// A place id from the geocoder, as linked from the chooser page.
+    Place(i64),
+    Coordinates(LatLong),
+}
+
+/// A resolved location of a forecast.
+#[derive(Clone)]
+pub struct Location {
+    pub name: String,
+    pub ll: LatLong,
+    /// The query parameters that name the location, to link to its views.
+    pub query: String,
+    /// The geocoder's record, unless the location was given as coordinates.
+    pub place: Option<Arc<Place>>,
+}
+
+impl Location {
+    pub fn at(ll: LatLong) -> Self {
+        let LatLong { lat, lng } = ll;
+        Self {
+            name: format!("{lat}, {lng}"),
+            ll,
+            query: format!("lat={lat}&lng={lng}"),
+            place: None,
+        }
+    }
+
+    /// The stored time zone of the place, if any.
+    pub fn timezone(&self) -> Option<String> {
+        self.place.as_ref().and_then(|place| place.timezone.clone())
+    }
+
+    fn city(place: Place) -> Self {
+        Self {
+            name: place.name.clone(),
+            ll: place.ll,
+            query: serde_urlencoded::to_string([("city", &place.name)]).unwrap_or_default(),
+            place: Some(Arc::new(place)),
+        }
+    }
+
+    fn place(place: Place) -> Self {
+        Self {
+            name: place.label(),
+            ll: place.ll,
+            query: format!("place={}", place.id),
+            place: Some(Arc::new(place)),
+        }
+    }
+}
+
+/// The outcome of looking up a city name.
+#[derive(Clone)]
+pub enum Resolution {
+    Found(Location),
+    /// Several places have the name, the best match first.
+    Ambiguous(Arc<[Place]>),
+}
+
+/// The columns of a stored place, as the fields of [`Place`].
+const PLACE_COLUMNS: &str = "place_id AS id, canonical_name AS name, lat, lng, \
+    country, country_code, admin1, timezone, elevation, population";
+
+/// The longest city name looked up, in characters.
+const MAX_CITY_LEN: usize = 100;
+
+/// The characters besides letters and spaces allowed in a city name.
+const CITY_PUNCTUATION: &str = "'’-.,()/";
+
+/// Checks a city name before it's looked up, and trims it.
+pub fn check_city(name: &str) -> Result<&str, Error> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Error::BadRequest("city must not be empty".to_owned()));
+    }
+
+    if name.chars().count() > MAX_CITY_LEN {
+        let message = format!("city must be at most {MAX_CITY_LEN} characters long");
+        return Err(Error::BadRequest(message));
+    }
+
+    let allowed = |c: char| {
+        c.is_alphabetic() || c == ' ' || is_combining_mark(c) || CITY_PUNCTUATION.contains(c)
+    };
+
+    match name.chars().find(|&c| !allowed(c)) {
+        Some(c) => Err(Error::BadRequest(format!(
+            "city must not contain {c:?}, only letters, spaces and {CITY_PUNCTUATION}",
+        ))),
+        None => Ok(name),
+    }
+}
+
+/// Reduces a city name to the form its spellings are matched by: trimmed,
+/// case-folded and without diacritics, so "  zurich" matches "Zürich".
+pub fn normalize(name: &str) -> String {
+    let words: Vec<_> = name.split_whitespace().collect();
+    let folded = caseless::default_case_fold_str(&words.join(" "));
+    folded
+        .nfd()
+        .filter(|&c| !is_combining_mark(c))
+        .nfc()
+        .collect()
+}
+
+/// Looks up a city name by its aliases in the database, then with the
+/// geocoder.
+///
+/// Only names that match a single place are stored, so an ambiguous name
+/// is asked about again rather than bound to the place someone picked.
+pub async fn resolve_city(app: &App, name: &str) -> Result<Resolution, Error> {
+    let pool = &app.pool;
+    let alias = normalize(name);
+    let place = sqlx::query_as(&format!(
+        "SELECT {PLACE_COLUMNS} FROM city_aliases JOIN cities ON cities.id = city_id \
+        WHERE alias = $1 AND place_id IS NOT NULL",
+    ))
+    .bind(&alias)
+    .fetch_optional(pool)
+    .await?;
+
+    if let Some(place) = place {
+        return Ok(Resolution::Found(Location::city(place)));
+    }
+
+    // The geocoder also matches prefixes, so prefer the places named exactly
+    let places = app.geocoder.search(name.trim()).await?;
+    let (exact, rest): (Vec<_>, Vec<_>) = places
+        .into_iter()
+        .partition(|place| normalize(&place.name) == alias);
+
+    let candidates = if exact.is_empty() { rest } else { exact };
+    let place = match candidates.as_slice() {
+        [] => return Err(Error::NoResultsFound),
+        [place] => place.clone(),
+        _ => return Ok(Resolution::Ambiguous(candidates.into())),
+    };
+
+    store(pool, &place.name, &alias, &place).await?;
+    Ok(Resolution::Found(Location::city(place)))
+}
+
+/// Looks up a place chosen by id in the database, then with the geocoder.
+pub async fn resolve_place(app: &App, id: i64) -> Result<Location, Error> {
+    let place = sqlx::query_as(&format!(
+        "SELECT {PLACE_COLUMNS} FROM cities WHERE place_id = $1",
+    ))
+    .bind(id)
+    .fetch_optional(&app.pool)
+    .await?;
+
+    if let Some(place) = place {
+        return Ok(Location::place(place));
+    }
+
+    // Stored under its label, so the name it shares with other places
+    // keeps asking which one was meant
+    let place = app.geocoder.place(id).await?;
+    let label = place.label();
+    store(&app.pool, &label, &normalize(&label), &place).await?;
+    Ok(Location::place(place))
+}
+
+/// Stores a place under a name and points the alias at it, refreshing the
+/// record of a place that's already stored.
+async fn store(pool: &PgPool, name: &str, alias: &str, place: &Place) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let id: i32 = sqlx::query_scalar(
+        "INSERT INTO cities (name, lat, lng, place_id, canonical_name, country, \
+            country_code, admin1, timezone, elevation, population) \
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) \
+        ON CONFLICT (place_id) DO UPDATE SET lat = EXCLUDED.lat, lng = EXCLUDED.lng, \
+            canonical_name = EXCLUDED.canonical_name, country = EXCLUDED.country, \
+            country_code = EXCLUDED.country_code, admin1 = EXCLUDED.admin1, \
+            timezone = EXCLUDED.timezone, elevation = EXCLUDED.elevation, \
+            population = EXCLUDED.population \
+        RETURNING id",
+    )
+    .bind(name)
+    .bind(place.ll.lat)
+    .bind(place.ll.lng)
+    .bind(place.id)
+    .bind(&place.name)
+    .bind(&place.country)
+    .bind(&place.country_code)
+    .bind(&place.admin1)
+    .bind(&place.timezone)
+    .bind(place.elevation)
+    .bind(place.population)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        "INSERT INTO city_aliases (alias, city_id) VALUES ($1, $2) \
+        ON CONFLICT (alias) DO UPDATE SET city_id = EXCLUDED.city_id",
+    )
+    .bind(alias)
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
+/// What [`cleanup`] changed.
+pub struct Cleanup {
+    pub aliases: u64,
+    pub merged: u64,
+}
+
+/// Gives every stored city an alias for its name and merges the rows whose
+/// names are spellings of the same city, keeping the ones with a place.
+pub async fn cleanup(pool: &PgPool) -> Result<Cleanup, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let cities: Vec<(i32, String)> =
+        sqlx::query_as("SELECT id, name FROM cities ORDER BY place_id IS NULL, id")
+            .fetch_all(&mut *tx)
+            .await?;
+
+    // The first row to claim an alias keeps it, the rest are duplicates
+    let mut aliases = 0;
+    for (id, name) in cities {
+        let res = sqlx::query(
+            "INSERT INTO city_aliases (alias, city_id) VALUES ($1, $2) \
+            ON CONFLICT (alias) DO NOTHING",
+        )
+        .bind(normalize(&name))
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+        aliases += res.rows_affected();
+
+        // Logged queries move to the row that keeps the alias, so the
+        // stats don't lose them when the duplicate is deleted
+        sqlx::query(
+            "UPDATE queries SET city_id = city_aliases.city_id FROM city_aliases \
+            WHERE alias = $1 AND queries.city_id = $2 AND city_aliases.city_id <> $2",
+        )
+        .bind(normalize(&name))
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let merged = sqlx::query(
+        "DELETE FROM cities \
+        WHERE NOT EXISTS (SELECT 1 FROM city_aliases WHERE city_id = cities.id)",
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    tx.commit().await?;
+    Ok(Cleanup { aliases, merged })
+}
+
+#[derive(Template)]
+#[template(path = "choose.html")]
+struct ChooseView<'a> {
+    city: &'a str,
+    choices: Vec<Choice>,
+}
+
+struct Choice {
+    label: String,
+    population: Option<i64>,
+    link: String,
+}
+
+#[derive(Serialize)]
+struct ChooseBody<'a> {
+    city: &'a str,
+    candidates: &'a [Place],
+}
+
+/// Lists the places that match an ambiguous name, with links that keep the
+/// rest of the query.
+pub fn choose(format: Format, query: &WeatherQuery, city: &str, places: &[Place]) -> Response {
+    #[derive(Serialize)]
+    struct PlaceQuery<'a> {
+        place: i64,
+        variables: Option<&'a str>,
+        view: View,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        units: Option<Units>,
+    }
+
+    let res = match format {
+        Format::Html => {
+            let choices = places
+                .iter()
+                .map(|place| {
+                    let query = PlaceQuery {
+                        place: place.id,
+                        variables: query.variables.as_deref(),
+                        view: query.view,
+                        units: query.units,
+                    };
+
+                    Choice {
+                        label: place.label(),
+                        population: place.population,
+                        link: format!(
+                            "/weather?{}",
+                            serde_urlencoded::to_string(query).unwrap_or_default(),
+                        ),
+                    }
+                })
+                .collect();
+
+            ChooseView { city, choices }.into_response()
+        }
+        Format::Json => Json(ChooseBody {
+            city,
+            candidates: places,
+        })
+        .into_response(),
+    };
+
+    (StatusCode::MULTIPLE_CHOICES, res).into_response()
+}
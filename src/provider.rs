@@ -0,0 +1,453 @@
+mod fixtures;
+mod open_meteo;
+mod outbound;
+
+pub use self::{fixtures::Fixtures, open_meteo::OpenMeteo, outbound::Outbound};
+
+use {
+    crate::{
+        config::{Provider, Upstream},
+        variable::Variable,
+    },
+    serde::{Deserialize, Serialize},
+    sqlx::FromRow,
+    std::{collections::HashMap, fmt, sync::Arc},
+    time::{Date, OffsetDateTime, PrimitiveDateTime, UtcOffset},
+};
+
+/// Finds places by name.
+#[async_trait::async_trait]
+pub trait Geocoder: Send + Sync {
+    /// Returns the places matching the name, the best match first.
+    async fn search(&self, name: &str) -> Result<Vec<Place>, Error>;
+
+    /// Looks up a place by the id the geocoder gave it.
+    async fn place(&self, id: i64) -> Result<Place, Error>;
+}
+
+/// Fetches the forecast for a location.
+#[async_trait::async_trait]
+pub trait WeatherProvider: Send + Sync {
+    async fn weather(&self, req: &WeatherRequest) -> Result<WeatherResponse, Error>;
+}
+
+/// The upstream service a request was made to.
+#[derive(Clone, Copy)]
+pub enum Service {
+    Geocoding,
+    Forecast,
+}
+
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Geocoding => write!(f, "geocoding service"),
+            Self::Forecast => write!(f, "forecast service"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Error {
+    /// The geocoder found no place with the name.
+    NoMatch,
+    /// The service didn't respond in time.
+    Timeout(Service),
+    /// The service responded with an error status.
+    Status(Service, u16),
+    /// The response couldn't be parsed.
+    Payload(Service, String),
+    /// The service couldn't be reached.
+    Unavailable(Service, String),
+    /// The service failed repeatedly, so it isn't asked for a while.
+    CircuitOpen(Service),
+    /// The requests per minute ceiling doesn't allow another request soon.
+    Throttled(Service),
+}
+
+impl Error {
+    /// Whether the service failed, rather than finding nothing.
+    pub fn is_failure(&self) -> bool {
+        !matches!(self, Self::NoMatch)
+    }
+
+    /// Whether the failure is likely to pass, so the request may be retried.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Timeout(_) | Self::Unavailable(..) => true,
+            Self::Status(_, status) => *status >= 500,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoMatch => write!(f, "no matching place found"),
+            Self::Timeout(service) => write!(f, "the {service} timed out"),
+            Self::Status(service, status) => write!(f, "the {service} responded with {status}"),
+            Self::Payload(service, err) => {
+                write!(f, "malformed response from the {service}: {err}")
+            }
+            Self::Unavailable(service, err) => write!(f, "the {service} is unavailable: {err}"),
+            Self::CircuitOpen(service) => {
+                write!(f, "the {service} is failing, not retrying it for a while")
+            }
+            Self::Throttled(service) => write!(f, "too many requests to the {service}"),
+        }
+    }
+}
+
+/// The data to fetch from a [`WeatherProvider`].
+pub struct WeatherRequest {
+    pub ll: LatLong,
+    /// The hourly variables, none to skip the hourly series.
+    pub hourly: Vec<Variable>,
+    /// Whether to fetch the daily summary.
+    pub daily: bool,
+    /// Whether to fetch the current conditions.
+    pub current: bool,
+    /// The IANA time zone to give the times in, else the location's own.
+    pub timezone: Option<String>,
+}
+
+/// Builds the geocoder and the weather provider selected in the config.
+pub fn from_config(
+    upstream: &Upstream,
+    outbound: &Arc<Outbound>,
+) -> (Arc<dyn Geocoder>, Arc<dyn WeatherProvider>) {
+    match upstream.provider {
+        Provider::OpenMeteo => {
+            let provider = Arc::new(OpenMeteo::new(upstream, Arc::clone(outbound)));
+            (provider.clone(), provider)
+        }
+        Provider::Fixtures => {
+            let dir = upstream.fixtures_dir.clone().unwrap_or_default();
+            let provider = Arc::new(Fixtures::new(dir));
+            (provider.clone(), provider)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, FromRow, Serialize)]
+pub struct LatLong {
+    #[serde(rename = "latitude")]
+    pub lat: f64,
+    #[serde(rename = "longitude")]
+    pub lng: f64,
+}
+
+/// A forecast, with its times local to the location.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct WeatherResponse {
+    #[serde(default)]
+    pub utc_offset_seconds: i32,
+    /// The IANA time zone of the times, such as `Europe/London`.
+    pub timezone: Option<String>,
+    /// Such as `BST`.
+    pub timezone_abbreviation: Option<String>,
+    #[serde(default)]
+    pub hourly: Hourly,
+    pub daily: Option<Daily>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<Current>,
+}
+
+impl WeatherResponse {
+    pub fn offset(&self) -> UtcOffset {
+        UtcOffset::from_whole_seconds(self.utc_offset_seconds).unwrap_or(UtcOffset::UTC)
+    }
+
+    /// The current time at the location.
+    pub fn now(&self) -> PrimitiveDateTime {
+        let now = OffsetDateTime::now_utc().to_offset(self.offset());
+        PrimitiveDateTime::new(now.date(), now.time())
+    }
+
+    /// The time zone and its offset, such as `Europe/London (BST, UTC+01:00)`.
+    pub fn zone(&self) -> String {
+        let offset = self.offset();
+        let (hours, minutes, _) = offset.as_hms();
+        let sign = if offset.is_negative() { '-' } else { '+' };
+        let offset = format!("UTC{sign}{:02}:{:02}", hours.abs(), minutes.abs());
+        let abbreviation = self
+            .timezone_abbreviation
+            .as_deref()
+            .filter(|abbreviation| !offset.ends_with(abbreviation) && *abbreviation != "GMT");
+
+        match (&self.timezone, abbreviation) {
+            (Some(timezone), Some(abbreviation)) => {
+                format!("{timezone} ({abbreviation}, {offset})")
+            }
+            (Some(timezone), None) if timezone != "GMT" => format!("{timezone} ({offset})"),
+            _ => offset,
+        }
+    }
+}
+
+/// The hourly series, each empty unless its variable was requested.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Hourly {
+    #[serde(default, with = "local_time")]
+    pub time: Vec<PrimitiveDateTime>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub temperature_2m: Vec<Option<f64>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub apparent_temperature: Vec<Option<f64>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub precipitation: Vec<Option<f64>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub precipitation_probability: Vec<Option<f64>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relative_humidity_2m: Vec<Option<f64>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cloud_cover: Vec<Option<f64>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wind_speed_10m: Vec<Option<f64>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wind_direction_10m: Vec<Option<f64>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub weather_code: Vec<Option<u8>>,
+}
+
+impl Hourly {
+    /// Returns the series of a numeric variable.
+    ///
+    /// Weather codes aren't numeric, so their series is always empty here.
+    pub fn series(&self, variable: Variable) -> &[Option<f64>] {
+        match variable {
+            Variable::Temperature => &self.temperature_2m,
+            Variable::ApparentTemperature => &self.apparent_temperature,
+            Variable::Precipitation => &self.precipitation,
+            Variable::PrecipitationProbability => &self.precipitation_probability,
+            Variable::RelativeHumidity => &self.relative_humidity_2m,
+            Variable::CloudCover => &self.cloud_cover,
+            Variable::WindSpeed => &self.wind_speed_10m,
+            Variable::WindDirection => &self.wind_direction_10m,
+            Variable::WeatherCode => &[],
+        }
+    }
+}
+
+/// The daily summary, one entry per day in each series.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Daily {
+    pub time: Vec<Date>,
+    #[serde(default)]
+    pub temperature_2m_min: Vec<Option<f64>>,
+    #[serde(default)]
+    pub temperature_2m_max: Vec<Option<f64>>,
+    #[serde(default)]
+    pub precipitation_sum: Vec<Option<f64>>,
+    #[serde(default, with = "local_time::option")]
+    pub sunrise: Vec<Option<PrimitiveDateTime>>,
+    #[serde(default, with = "local_time::option")]
+    pub sunset: Vec<Option<PrimitiveDateTime>>,
+    #[serde(default)]
+    pub weather_code: Vec<Option<u8>>,
+}
+
+impl Daily {
+    /// Aggregates the hourly series for providers without a daily summary.
+    ///
+    /// Sunrise and sunset can't be derived from the hourly data and are left
+    /// empty. The dominant weather code is the most frequent one of the day,
+    /// preferring the more severe (higher) code on ties.
+    pub fn from_hourly(hourly: &Hourly) -> Self {
+        let mut daily = Self {
+            time: vec![],
+            temperature_2m_min: vec![],
+            temperature_2m_max: vec![],
+            precipitation_sum: vec![],
+            sunrise: vec![],
+            sunset: vec![],
+            weather_code: vec![],
+        };
+
+        let mut start = 0;
+        while start < hourly.time.len() {
+            let date = hourly.time[start].date();
+            let end = hourly.time[start..]
+                .iter()
+                .position(|time| time.date() != date)
+                .map_or(hourly.time.len(), |len| start + len);
+
+            let values = |series: &[Option<f64>]| -> Vec<f64> {
+                series
+                    .get(start..end)
+                    .unwrap_or_default()
+                    .iter()
+                    .flatten()
+                    .copied()
+                    .collect()
+            };
+
+            let temperatures = values(&hourly.temperature_2m);
+            let min = temperatures.iter().copied().reduce(f64::min);
+            let max = temperatures.iter().copied().reduce(f64::max);
+            let precipitation = values(&hourly.precipitation);
+            let sum = (!precipitation.is_empty()).then(|| precipitation.iter().sum());
+
+            let mut codes: HashMap<u8, usize> = HashMap::new();
+            let day_codes = hourly.weather_code.get(start..end).unwrap_or_default();
+            for &code in day_codes.iter().flatten() {
+                *codes.entry(code).or_default() += 1;
+            }
+
+            let code = codes
+                .into_iter()
+                .max_by_key(|&(code, count)| (count, code))
+                .map(|(code, _)| code);
+
+            daily.time.push(date);
+            daily.temperature_2m_min.push(min);
+            daily.temperature_2m_max.push(max);
+            daily.precipitation_sum.push(sum);
+            daily.sunrise.push(None);
+            daily.sunset.push(None);
+            daily.weather_code.push(code);
+            start = end;
+        }
+
+        daily
+    }
+}
+
+/// The conditions at the latest observation.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Current {
+    /// The local time of the observation.
+    #[serde(with = "local_time::format")]
+    pub time: PrimitiveDateTime,
+    pub temperature_2m: Option<f64>,
+    pub apparent_temperature: Option<f64>,
+    pub relative_humidity_2m: Option<f64>,
+    pub wind_speed_10m: Option<f64>,
+    pub wind_direction_10m: Option<f64>,
+    pub weather_code: Option<u8>,
+}
+
+/// A place found by the [`Geocoder`].
+#[derive(Clone, Deserialize, FromRow, Serialize)]
+pub struct Place {
+    pub id: i64,
+    pub name: String,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub ll: LatLong,
+    pub country: Option<String>,
+    /// The ISO 3166-1 alpha-2 code of the country.
+    pub country_code: Option<String>,
+    /// The first level administrative region, such as a state.
+    pub admin1: Option<String>,
+    /// The IANA time zone, such as `Europe/London`.
+    pub timezone: Option<String>,
+    /// In meters above sea level.
+    pub elevation: Option<f64>,
+    pub population: Option<i64>,
+}
+
+impl Place {
+    /// The name along with the region and country, to tell places apart.
+    pub fn label(&self) -> String {
+        let mut label = self.name.clone();
+        for part in self.region() {
+            label += ", ";
+            label += part;
+        }
+
+        label
+    }
+
+    /// The metadata shown along with the name.
+    pub fn details(&self) -> Vec<String> {
+        let LatLong { lat, lng } = self.ll;
+        let mut details = vec![];
+        let region = self.region().collect::<Vec<_>>().join(", ");
+        match (&self.country_code, region.is_empty()) {
+            (Some(code), false) => details.push(format!("{region} ({code})")),
+            (Some(code), true) => details.push(code.clone()),
+            (None, false) => details.push(region),
+            (None, true) => {}
+        }
+
+        details.push(format!("{lat}, {lng}"));
+        if let Some(elevation) = self.elevation {
+            details.push(format!("{elevation} m"));
+        }
+
+        if let Some(timezone) = &self.timezone {
+            details.push(timezone.clone());
+        }
+
+        if let Some(population) = self.population {
+            details.push(format!("population {population}"));
+        }
+
+        details
+    }
+
+    /// The region and country, skipping a region named like the place.
+    fn region(&self) -> impl Iterator<Item = &str> {
+        let admin1 = self.admin1.as_deref().filter(|admin1| *admin1 != self.name);
+        [admin1, self.country.as_deref()].into_iter().flatten()
+    }
+}
+
+#[derive(Deserialize)]
+struct GeoResponse {
+    #[serde(default)]
+    results: Vec<Place>,
+}
+
+/// (De)serializes local times in the Open-Meteo format, `2023-10-01T13:00`.
+mod local_time {
+    use {
+        serde::{Deserialize, Deserializer, Serialize, Serializer},
+        time::PrimitiveDateTime,
+    };
+
+    time::serde::format_description!(
+        pub format,
+        PrimitiveDateTime,
+        "[year]-[month]-[day]T[hour]:[minute]"
+    );
+
+    #[derive(Deserialize, Serialize)]
+    #[serde(transparent)]
+    struct Time(#[serde(with = "format")] PrimitiveDateTime);
+
+    pub fn serialize<S: Serializer>(times: &[PrimitiveDateTime], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(times.iter().map(|&time| Time(time)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<Vec<PrimitiveDateTime>, D::Error> {
+        let times = Vec::<Time>::deserialize(d)?;
+        Ok(times.into_iter().map(|Time(time)| time).collect())
+    }
+
+    /// For series with gaps, such as sunrises in polar regions.
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            times: &[Option<PrimitiveDateTime>],
+            s: S,
+        ) -> Result<S::Ok, S::Error> {
+            s.collect_seq(times.iter().map(|time| time.map(Time)))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            d: D,
+        ) -> Result<Vec<Option<PrimitiveDateTime>>, D::Error> {
+            let times = Vec::<Option<Time>>::deserialize(d)?;
+            Ok(times
+                .into_iter()
+                .map(|time| time.map(|Time(time)| time))
+                .collect())
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use {
+    super::{
+        Error, GeoResponse, Geocoder, Place, Service, WeatherProvider, WeatherRequest,
+        WeatherResponse,
+    },
+    serde::de::DeserializeOwned,
+    std::{
+        ffi::OsStr,
+        io::{self, ErrorKind},
+        path::{is_separator, Component, Path, PathBuf},
+    },
+    tokio::fs,
+};
+
+/// Serves recorded upstream responses from a directory.
+///
+/// Geocoding results are read from `geocoding/<city>.json`, places by id
+/// are looked up in all of those, and the forecast is read from
+/// `forecast.json`, all in the Open-Meteo response format.
+pub struct Fixtures {
+    dir: PathBuf,
+}
+
+impl Fixtures {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    async fn read<T>(service: Service, path: &Path) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let content = fs::read(path).await.map_err(|err| match err.kind() {
+            ErrorKind::NotFound if matches!(service, Service::Geocoding) => Error::NoMatch,
+            _ => Error::Unavailable(service, format!("{}: {err}", path.display())),
+        })?;
+
+        serde_json::from_slice(&content).map_err(|err| Error::Payload(service, err.to_string()))
+    }
+}
+
+#[async_trait::async_trait]
+impl Geocoder for Fixtures {
+    async fn search(&self, name: &str) -> Result<Vec<Place>, Error> {
+        // Don't let the city name escape the fixtures directory
+        let file = name.to_lowercase() + ".json";
+        let single = matches!(
+            Path::new(&file).components().collect::<Vec<_>>()[..],
+            [Component::Normal(_)]
+        );
+
+        if name.contains(is_separator) || name.contains(['/', '\\']) || !single {
+            return Err(Error::NoMatch);
+        }
+
+        let path = self.dir.join("geocoding").join(file);
+        let res: GeoResponse = Self::read(Service::Geocoding, &path).await?;
+        Ok(res.results)
+    }
+
+    async fn place(&self, id: i64) -> Result<Place, Error> {
+        let dir = self.dir.join("geocoding");
+        let unavailable = |err: io::Error| {
+            Error::Unavailable(Service::Geocoding, format!("{}: {err}", dir.display()))
+        };
+
+        let mut entries = fs::read_dir(&dir).await.map_err(unavailable)?;
+        while let Some(entry) = entries.next_entry().await.map_err(unavailable)? {
+            // Skip stray files such as a README or editor swap files
+            let path = entry.path();
+            if path.extension().and_then(OsStr::to_str) != Some("json") {
+                continue;
+            }
+
+            let res: GeoResponse = Self::read(Service::Geocoding, &path).await?;
+            if let Some(place) = res.results.into_iter().find(|place| place.id == id) {
+                return Ok(place);
+            }
+        }
+
+        Err(Error::NoMatch)
+    }
+}
+
+#[async_trait::async_trait]
+impl WeatherProvider for Fixtures {
+    async fn weather(&self, _: &WeatherRequest) -> Result<WeatherResponse, Error> {
+        Self::read(Service::Forecast, &self.dir.join("forecast.json")).await
+    }
+}
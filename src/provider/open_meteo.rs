@@ -0,0 +1,115 @@
+use {
+    super::{
+        outbound::{self, Outbound},
+        Error, GeoResponse, Geocoder, LatLong, Place, Service, WeatherProvider, WeatherRequest,
+        WeatherResponse,
+    },
+    crate::config::Upstream,
+    reqwest::Url,
+    serde::Serialize,
+    std::sync::Arc,
+};
+
+/// The [Open-Meteo](https://open-meteo.com) geocoding and forecast APIs.
+pub struct OpenMeteo {
+    outbound: Arc<Outbound>,
+    geocoding_url: Url,
+    forecast_url: Url,
+}
+
+impl OpenMeteo {
+    pub fn new(upstream: &Upstream, outbound: Arc<Outbound>) -> Self {
+        let parse = |url: &str| Url::parse(url).expect("the config validates the upstream URLs");
+        Self {
+            outbound,
+            geocoding_url: parse(&upstream.geocoding_url),
+            forecast_url: parse(&upstream.forecast_url),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SearchQuery<'a> {
+    name: &'a str,
+    count: usize,
+    language: &'static str,
+    format: &'static str,
+}
+
+#[derive(Serialize)]
+struct GetQuery {
+    id: i64,
+    language: &'static str,
+    format: &'static str,
+}
+
+#[derive(Serialize)]
+struct ForecastQuery<'a> {
+    latitude: f64,
+    longitude: f64,
+    /// Comma separated variable names.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hourly: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    daily: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    current: Option<&'static str>,
+    /// An IANA time zone, or `auto` for the location's own.
+    timezone: &'a str,
+}
+
+#[async_trait::async_trait]
+impl Geocoder for OpenMeteo {
+    async fn search(&self, name: &str) -> Result<Vec<Place>, Error> {
+        const CANDIDATES: usize = 10;
+
+        let query = SearchQuery {
+            name,
+            count: CANDIDATES,
+            language: "en",
+            format: "json",
+        };
+
+        let endpoint = outbound::endpoint(&self.geocoding_url, &["v1", "search"], &query);
+        let res: GeoResponse = self.outbound.get(Service::Geocoding, &endpoint).await?;
+        Ok(res.results)
+    }
+
+    async fn place(&self, id: i64) -> Result<Place, Error> {
+        let query = GetQuery {
+            id,
+            language: "en",
+            format: "json",
+        };
+
+        let endpoint = outbound::endpoint(&self.geocoding_url, &["v1", "get"], &query);
+        match self.outbound.get(Service::Geocoding, &endpoint).await {
+            Err(Error::Status(_, 400 | 404)) => Err(Error::NoMatch),
+            res => res,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl WeatherProvider for OpenMeteo {
+    async fn weather(&self, req: &WeatherRequest) -> Result<WeatherResponse, Error> {
+        const DAILY: &str =
+            "temperature_2m_min,temperature_2m_max,precipitation_sum,sunrise,sunset,weather_code";
+        const CURRENT: &str = "temperature_2m,apparent_temperature,relative_humidity_2m,\
+            wind_speed_10m,wind_direction_10m,weather_code";
+
+        let LatLong { lat, lng } = req.ll;
+        let hourly: Vec<_> = req.hourly.iter().map(|var| var.api_name()).collect();
+        let query = ForecastQuery {
+            latitude: lat,
+            longitude: lng,
+            hourly: (!hourly.is_empty()).then(|| hourly.join(",")),
+            daily: req.daily.then_some(DAILY),
+            current: req.current.then_some(CURRENT),
+            timezone: req.timezone.as_deref().unwrap_or("auto"),
+        };
+
+        let endpoint = outbound::endpoint(&self.forecast_url, &["v1", "forecast"], &query);
+        self.outbound.get(Service::Forecast, &endpoint).await
+    }
+}
@@ -0,0 +1,236 @@
+use {
+    super::{Error, Service},
+    crate::config::Upstream,
+    rand::Rng,
+    reqwest::{Client, Url},
+    serde::{de::DeserializeOwned, Serialize},
+    std::{
+        sync::{Mutex, PoisonError},
+        time::{Duration, Instant},
+    },
+};
+
+/// The HTTP client shared by the upstream requests, which retries them
+/// with backoff, keeps them under a requests per minute ceiling and fails
+/// fast while a service is down.
+pub struct Outbound {
+    http: Client,
+    retries: u32,
+    backoff: Duration,
+    /// The longest a request waits for the ceiling before giving up.
+    max_wait: Duration,
+    throttle: Mutex<Throttle>,
+    geocoding: Breaker,
+    forecast: Breaker,
+}
+
+impl Outbound {
+    pub fn new(upstream: &Upstream) -> Self {
+        let http = Client::builder()
+            .connect_timeout(upstream.connect_timeout())
+            .timeout(upstream.timeout())
+            .build()
+            .expect("build http client");
+
+        let per_minute = f64::from(upstream.requests_per_minute);
+        let breaker = || Breaker {
+            threshold: upstream.breaker_threshold,
+            cooldown: upstream.breaker_cooldown(),
+            state: Mutex::default(),
+        };
+
+        Self {
+            http,
+            retries: upstream.retries,
+            backoff: upstream.retry_backoff(),
+            max_wait: upstream.timeout(),
+            throttle: Mutex::new(Throttle {
+                // Allow a second's worth of requests at once
+                capacity: f64::max(1., per_minute / 60.),
+                rate: per_minute / 60.,
+                tokens: f64::max(1., per_minute / 60.),
+                updated: Instant::now(),
+            }),
+            geocoding: breaker(),
+            forecast: breaker(),
+        }
+    }
+
+    pub async fn get<T>(&self, service: Service, endpoint: &Url) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let breaker = self.breaker(service);
+        let mut attempt = 0;
+        loop {
+            if !breaker.allow() {
+                return Err(Error::CircuitOpen(service));
+            }
+
+            self.throttle(service).await?;
+            let res = self.send(service, endpoint).await;
+            let failed = matches!(&res, Err(err) if err.is_transient());
+            breaker.record(failed);
+            if !failed || attempt == self.retries || breaker.is_open() {
+                return res;
+            }
+
+            tokio::time::sleep(self.backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    /// Whether requests to the service currently fail fast.
+    pub fn is_open(&self, service: Service) -> bool {
+        self.breaker(service).is_open()
+    }
+
+    async fn send<T>(&self, service: Service, endpoint: &Url) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let classify = |err: reqwest::Error| {
+            if err.is_timeout() {
+                Error::Timeout(service)
+            } else if let Some(status) = err.status() {
+                Error::Status(service, status.as_u16())
+            } else if err.is_decode() {
+                Error::Payload(service, err.to_string())
+            } else {
+                Error::Unavailable(service, err.to_string())
+            }
+        };
+
+        let res = self
+            .http
+            .get(endpoint.clone())
+            .send()
+            .await
+            .map_err(classify)?;
+        let res = res.error_for_status().map_err(classify)?;
+        res.json().await.map_err(classify)
+    }
+
+    fn breaker(&self, service: Service) -> &Breaker {
+        match service {
+            Service::Geocoding => &self.geocoding,
+            Service::Forecast => &self.forecast,
+        }
+    }
+
+    /// Exponential backoff with jitter, so clients that failed together
+    /// don't retry together.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self.backoff.saturating_mul(2u32.saturating_pow(attempt));
+        backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.))
+    }
+
+    /// Waits for the turn of a request under the ceiling.
+    async fn throttle(&self, service: Service) -> Result<(), Error> {
+        let wait = {
+            let mut throttle = self.throttle.lock().unwrap_or_else(PoisonError::into_inner);
+            throttle.reserve(self.max_wait)
+        };
+
+        let wait = wait.ok_or(Error::Throttled(service))?;
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+
+        Ok(())
+    }
+}
+
+/// Builds the URL of an endpoint below the base URL of a service, with the
+/// fields of `query` as its percent-encoded query string.
+pub fn endpoint<Q>(base: &Url, path: &[&str], query: &Q) -> Url
+where
+    Q: Serialize,
+{
+    let mut url = base.clone();
+    if let Ok(mut segments) = url.path_segments_mut() {
+        segments.pop_if_empty().extend(path);
+    }
+
+    let query = serde_urlencoded::to_string(query).expect("query parameters are flat");
+    url.set_query(Some(&query));
+    url
+}
+
+/// A token bucket that queues the requests over it.
+struct Throttle {
+    capacity: f64,
+    /// Tokens added per second.
+    rate: f64,
+    /// Negative while requests are queued.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Throttle {
+    /// Takes a token and returns how long to wait for it, unless that is
+    /// longer than `max_wait`.
+    fn reserve(&mut self, max_wait: Duration) -> Option<Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = f64::min(self.capacity, self.tokens + elapsed * self.rate);
+        self.updated = now;
+
+        let wait = Duration::from_secs_f64(f64::max(0., (1. - self.tokens) / self.rate));
+        if wait > max_wait {
+            return None;
+        }
+
+        self.tokens -= 1.;
+        Some(wait)
+    }
+}
+
+struct Breaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    /// Consecutive failures.
+    failures: u32,
+    /// Until when requests fail fast, once the failures reach the threshold.
+    open_until: Option<Instant>,
+}
+
+impl Breaker {
+    fn allow(&self) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        match state.open_until {
+            Some(until) if now < until => false,
+            Some(_) => {
+                // Let a single request through to probe the service, and
+                // keep failing the rest until it's known to be back
+                state.open_until = Some(now + self.cooldown);
+                true
+            }
+            None => true,
+        }
+    }
+
+    fn record(&self, failed: bool) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if !failed {
+            *state = BreakerState::default();
+            return;
+        }
+
+        state.failures += 1;
+        if state.failures >= self.threshold {
+            state.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.open_until.is_some_and(|until| Instant::now() < until)
+    }
+}
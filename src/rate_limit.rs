@@ -0,0 +1,154 @@
+//! Per-client token buckets that keep a single client from using up our
+//! upstream quota.
+
+use {
+    crate::{
+        api_key::ApiKey,
+        config::{self, Limit},
+        error::Error,
+        App,
+    },
+    axum::{
+        extract::{ConnectInfo, MatchedPath, State},
+        headers::{authorization::Bearer, Authorization, HeaderMapExt},
+        http::Request,
+        middleware::Next,
+        response::{IntoResponse, Response},
+    },
+    std::{
+        collections::HashMap,
+        net::{IpAddr, SocketAddr},
+        sync::{Mutex, PoisonError},
+        time::{Duration, Instant},
+    },
+};
+
+/// How many buckets are kept before the full ones are dropped, since a
+/// full bucket is the same as a missing one.
+const MAX_BUCKETS: usize = 10_000;
+
+pub struct Limiter {
+    limits: HashMap<String, Limit>,
+    buckets: Mutex<HashMap<(String, Client), Bucket>>,
+}
+
+impl Limiter {
+    pub fn new(config: &config::RateLimit) -> Self {
+        Self {
+            limits: config.routes.clone().into_iter().collect(),
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// Whether requests to the route are limited.
+    fn limits(&self, route: &str) -> bool {
+        self.limits.contains_key(route)
+    }
+
+    /// Takes a token from the bucket of the client for the route, or
+    /// returns how long until the next one is available.
+    fn take(&self, route: &str, client: Client) -> Result<(), Duration> {
+        let Some(&limit) = self.limits.get(route) else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        if buckets.len() >= MAX_BUCKETS {
+            buckets.retain(|(route, _), bucket| match self.limits.get(route) {
+                Some(&limit) => !bucket.refill(limit, now),
+                None => false,
+            });
+        }
+
+        let bucket = buckets.entry((route.to_owned(), client)).or_insert(Bucket {
+            tokens: f64::from(limit.burst),
+            updated: now,
+        });
+
+        bucket.refill(limit, now);
+        if bucket.tokens >= 1. {
+            bucket.tokens -= 1.;
+            Ok(())
+        } else {
+            let rate = f64::from(limit.per_minute) / 60.;
+            Err(Duration::from_secs_f64((1. - bucket.tokens) / rate))
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Adds the tokens accrued since the last update and returns whether
+    /// the bucket is full.
+    fn refill(&mut self, limit: Limit, now: Instant) -> bool {
+        let rate = f64::from(limit.per_minute) / 60.;
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        let burst = f64::from(limit.burst);
+        self.tokens = f64::min(burst, self.tokens + elapsed * rate);
+        self.updated = now;
+        self.tokens >= burst
+    }
+}
+
+/// Who a request is counted against.
+#[derive(PartialEq, Eq, Hash)]
+enum Client {
+    /// The id of a valid API key, so clients behind one address don't
+    /// share a limit.
+    Key(i32),
+    Ip(Option<IpAddr>),
+}
+
+impl Client {
+    fn ip<B>(req: &Request<B>) -> Self {
+        let addr = req.extensions().get::<ConnectInfo<SocketAddr>>();
+        Self::Ip(addr.map(|ConnectInfo(addr)| addr.ip()))
+    }
+}
+
+/// Rejects requests over the limit of their route with 429.
+///
+/// Bearer tokens are checked first, so made up ones are counted against
+/// the address rather than getting a bucket each. The key is left in the
+/// extensions for the handler, so it isn't looked up twice.
+pub async fn limit<B: Send>(
+    State(app): State<App>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let route = req.extensions().get::<MatchedPath>();
+    let route = route.map_or("", MatchedPath::as_str).to_owned();
+    if !app.limiter.limits(&route) {
+        return next.run(req).await;
+    }
+
+    let key = match req.headers().typed_get::<Authorization<Bearer>>() {
+        Some(Authorization(bearer)) => {
+            match ApiKey::authenticate(&app.pool, bearer.token()).await {
+                Ok(key) => key,
+                Err(err) => return Error::from(err).into_response(),
+            }
+        }
+        None => None,
+    };
+
+    let client = match &key {
+        Some(key) => Client::Key(key.id),
+        None => Client::ip(&req),
+    };
+
+    if let Err(retry_after) = app.limiter.take(&route, client) {
+        return Error::RateLimited(retry_after).into_response();
+    }
+
+    if let Some(key) = key {
+        req.extensions_mut().insert(key);
+    }
+
+    next.run(req).await
+}
@@ -0,0 +1,280 @@
+//! Login sessions, kept in the database and referenced by a signed cookie.
+
+use {
+    crate::{api::Format, config, error::Error, user::User, App},
+    askama_axum::Template,
+    axum::{
+        extract::{
+            rejection::{FormRejection, QueryRejection},
+            Query, State,
+        },
+        http::{
+            header, request::Parts, uri::PathAndQuery, HeaderMap, HeaderValue, Method, StatusCode,
+        },
+        response::{AppendHeaders, IntoResponse, Redirect, Response},
+        Form,
+    },
+    cookie::{time, Cookie, CookieJar, Key, SameSite},
+    rand::{distributions::Alphanumeric, Rng},
+    serde::Deserialize,
+    sqlx::PgPool,
+    std::time::Duration,
+    subtle::ConstantTimeEq,
+};
+
+const SESSION_COOKIE: &str = "forecast_session";
+const CSRF_COOKIE: &str = "forecast_csrf";
+
+/// Where to go after signing in, unless the login page was given a target.
+const DEFAULT_NEXT: &str = "/stats";
+
+/// Builds the key that signs the cookies.
+pub fn key(config: &config::Session) -> Key {
+    match &config.secret {
+        Some(secret) => Key::derive_from(secret.as_bytes()),
+        None => {
+            eprintln!("session.secret is not set, sessions won't survive a restart");
+            Key::generate()
+        }
+    }
+}
+
+/// Looks up the user of the session cookie, if it's valid and unexpired.
+pub async fn user(app: &App, headers: &HeaderMap) -> Result<Option<User>, sqlx::Error> {
+    let Some(id) = signed_cookie(headers, &app.key, SESSION_COOKIE) else {
+        return Ok(None);
+    };
+
+    let user = sqlx::query_as(
+        "SELECT users.id, users.name, users.role, sessions.csrf_token AS csrf \
+        FROM sessions JOIN users ON users.id = sessions.user_id \
+        WHERE sessions.id = $1 AND sessions.expires_at > now() AND NOT users.disabled",
+    )
+    .bind(id)
+    .fetch_optional(&app.pool)
+    .await?;
+
+    Ok(user)
+}
+
+/// The login page to send a browser to instead of asking for Basic auth.
+pub fn login_redirect(parts: &Parts) -> Option<String> {
+    let accept = parts.headers.get(header::ACCEPT)?.to_str().ok()?;
+    let browser = parts.method == Method::GET
+        && !parts.uri.path().starts_with("/api/")
+        && accept.contains("text/html")
+        && Format::from_headers(&parts.headers) == Format::Html;
+
+    if !browser {
+        return None;
+    }
+
+    let next = parts.uri.path_and_query()?.as_str();
+    let query = serde_urlencoded::to_string([("next", next)]).ok()?;
+    Some(format!("/login?{query}"))
+}
+
+#[derive(Deserialize)]
+pub struct LoginQuery {
+    next: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginView {
+    next: String,
+    csrf: String,
+    error: Option<&'static str>,
+}
+
+pub async fn login_page(
+    query: Result<Query<LoginQuery>, QueryRejection>,
+    State(app): State<App>,
+) -> Result<Response, Error> {
+    let Query(query) = query?;
+    Ok(login_form(&app, local_path(query.next), None))
+}
+
+/// Renders the login form along with a fresh CSRF token, which is also set
+/// as a cookie for the post to be checked against.
+fn login_form(app: &App, next: String, error: Option<&'static str>) -> Response {
+    let csrf = token();
+    let cookie = set_cookie(app, CSRF_COOKIE, csrf.clone(), None);
+    let view = LoginView { next, csrf, error };
+    (AppendHeaders([(header::SET_COOKIE, cookie)]), view).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    name: String,
+    password: String,
+    csrf: String,
+    next: Option<String>,
+}
+
+pub async fn login(
+    State(app): State<App>,
+    headers: HeaderMap,
+    form: Result<Form<LoginForm>, FormRejection>,
+) -> Result<Response, Error> {
+    let Form(form) = form?;
+    let expected = signed_cookie(&headers, &app.key, CSRF_COOKIE);
+    check_csrf(expected.as_deref(), &form.csrf)?;
+
+    let next = local_path(form.next);
+    let Some(user) = User::authenticate(&app.pool, &form.name, &form.password).await? else {
+        let mut res = login_form(&app, next, Some("Invalid name or password"));
+        *res.status_mut() = StatusCode::UNAUTHORIZED;
+        return Ok(res);
+    };
+
+    let ttl = app.config.session.ttl();
+    let id = create(&app.pool, &user, ttl).await?;
+    let cookies = AppendHeaders([
+        (
+            header::SET_COOKIE,
+            set_cookie(&app, SESSION_COOKIE, id, Some(ttl)),
+        ),
+        (header::SET_COOKIE, remove_cookie(CSRF_COOKIE)),
+    ]);
+
+    let location =
+        HeaderValue::from_str(&next).unwrap_or_else(|_| HeaderValue::from_static(DEFAULT_NEXT));
+    let res = (StatusCode::SEE_OTHER, [(header::LOCATION, location)]);
+    Ok((cookies, res).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct LogoutForm {
+    csrf: String,
+}
+
+pub async fn logout(
+    State(app): State<App>,
+    headers: HeaderMap,
+    form: Result<Form<LogoutForm>, FormRejection>,
+) -> Result<Response, Error> {
+    let Form(form) = form?;
+    if let Some(id) = signed_cookie(&headers, &app.key, SESSION_COOKIE) {
+        let expected: Option<String> =
+            sqlx::query_scalar("SELECT csrf_token FROM sessions WHERE id = $1")
+                .bind(&id)
+                .fetch_optional(&app.pool)
+                .await?;
+
+        // A session that is already gone has nothing left to protect
+        if expected.is_some() {
+            check_csrf(expected.as_deref(), &form.csrf)?;
+            sqlx::query("DELETE FROM sessions WHERE id = $1")
+                .bind(&id)
+                .execute(&app.pool)
+                .await?;
+        }
+    }
+
+    let cookies = AppendHeaders([(header::SET_COOKIE, remove_cookie(SESSION_COOKIE))]);
+    Ok((cookies, Redirect::to("/login")).into_response())
+}
+
+async fn create(pool: &PgPool, user: &User, ttl: Duration) -> Result<String, sqlx::Error> {
+    sqlx::query("DELETE FROM sessions WHERE expires_at <= now()")
+        .execute(pool)
+        .await?;
+
+    let id = token();
+    sqlx::query(
+        "INSERT INTO sessions (id, user_id, csrf_token, expires_at) \
+        VALUES ($1, $2, $3, now() + make_interval(secs => $4))",
+    )
+    .bind(&id)
+    .bind(user.id)
+    .bind(token())
+    .bind(ttl.as_secs_f64())
+    .execute(pool)
+    .await?;
+
+    Ok(id)
+}
+
+pub fn check_csrf(expected: Option<&str>, token: &str) -> Result<(), Error> {
+    let valid = expected.is_some_and(|expected| {
+        !token.is_empty() && bool::from(expected.as_bytes().ct_eq(token.as_bytes()))
+    });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::Forbidden("invalid CSRF token".to_owned()))
+    }
+}
+
+/// Keeps the redirect after signing in on this site.
+///
+/// Only printable ASCII is accepted, since browsers drop tabs and newlines
+/// from URLs, which would turn `/\t/host` into `//host`.
+fn local_path(next: Option<String>) -> String {
+    let local = |next: &str| {
+        next.starts_with('/')
+            && !next.starts_with("//")
+            && !next.contains('\\')
+            && next.chars().all(|c| c.is_ascii_graphic())
+            && next.parse::<PathAndQuery>().is_ok()
+    };
+
+    match next {
+        Some(next) if local(&next) => next,
+        _ => DEFAULT_NEXT.to_owned(),
+    }
+}
+
+/// A random token for session ids and CSRF tokens.
+fn token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+fn signed_cookie(headers: &HeaderMap, key: &Key, name: &str) -> Option<String> {
+    let mut jar = CookieJar::new();
+    let values = headers.get_all(header::COOKIE).iter();
+    for value in values.filter_map(|value| value.to_str().ok()) {
+        for cookie in Cookie::split_parse(value).flatten() {
+            jar.add_original(cookie.into_owned());
+        }
+    }
+
+    let cookie = jar.signed(key).get(name)?;
+    Some(cookie.value().to_owned())
+}
+
+fn set_cookie(
+    app: &App,
+    name: &'static str,
+    value: String,
+    max_age: Option<Duration>,
+) -> HeaderValue {
+    let mut cookie = Cookie::build((name, value))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(app.config.session.secure_cookies);
+
+    if let Some(max_age) = max_age {
+        cookie = cookie.max_age(time::Duration::try_from(max_age).unwrap_or(time::Duration::MAX));
+    }
+
+    let mut jar = CookieJar::new();
+    jar.signed_mut(&app.key).add(cookie);
+    let cookie = jar.get(name).expect("the cookie was just added");
+    HeaderValue::from_str(&cookie.to_string()).expect("signed cookies are valid header values")
+}
+
+fn remove_cookie(name: &'static str) -> HeaderValue {
+    let cookie = Cookie::build((name, ""))
+        .path("/")
+        .max_age(time::Duration::ZERO);
+
+    HeaderValue::from_str(&cookie.to_string()).expect("valid header value")
+}
@@ -0,0 +1,190 @@
+//! The log of weather requests and the usage statistics drawn from it.
+
+use {
+    crate::api_key::Caller,
+    serde::{Deserialize, Serialize},
+    sqlx::{FromRow, PgPool},
+    std::{net::IpAddr, time::Duration},
+};
+
+/// How many of the most requested cities are listed.
+const TOP_CITIES: i64 = 10;
+
+/// What is recorded about a weather request.
+#[derive(Default)]
+pub struct Entry {
+    /// The geocoder's id of the place the forecast was for.
+    pub place_id: Option<i64>,
+    pub cache_hit: Option<bool>,
+    /// Whether an upstream service failed, even if a stale forecast was
+    /// served instead.
+    pub upstream_error: bool,
+    pub client: String,
+}
+
+impl Entry {
+    /// Names the client by its credentials, or else its address.
+    pub fn new(caller: Option<&Caller>, ip: Option<IpAddr>) -> Self {
+        let client = match (caller, ip) {
+            (Some(Caller::User(user)), _) => format!("user:{}", user.name),
+            (Some(Caller::Key(key)), _) => format!("key:{}", key.name),
+            (None, Some(ip)) => ip.to_string(),
+            (None, None) => "unknown".to_owned(),
+        };
+
+        Self {
+            client,
+            ..Self::default()
+        }
+    }
+
+    pub async fn record(
+        &self,
+        pool: &PgPool,
+        status: u16,
+        latency: Duration,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO queries (city_id, cache_hit, upstream_error, status, latency_ms, client) \
+            VALUES ((SELECT id FROM cities WHERE place_id = $1), $2, $3, $4, $5, $6)",
+        )
+        .bind(self.place_id)
+        .bind(self.cache_hit)
+        .bind(self.upstream_error)
+        .bind(status as i16)
+        .bind(latency.as_secs_f64() * 1000.)
+        .bind(&self.client)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// The span of time the statistics cover, up to now.
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Window {
+    Hour,
+    #[default]
+    Day,
+    Week,
+    Month,
+}
+
+impl Window {
+    pub const ALL: [Self; 4] = [Self::Hour, Self::Day, Self::Week, Self::Month];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Hour => "hour",
+            Self::Day => "day",
+            Self::Week => "week",
+            Self::Month => "month",
+        }
+    }
+
+    fn duration(self) -> Duration {
+        const HOUR: u64 = 60 * 60;
+        Duration::from_secs(match self {
+            Self::Hour => HOUR,
+            Self::Day => 24 * HOUR,
+            Self::Week => 7 * 24 * HOUR,
+            Self::Month => 30 * 24 * HOUR,
+        })
+    }
+
+    /// The unit the requests are counted per.
+    pub fn unit(self) -> &'static str {
+        self.period().0
+    }
+
+    /// The unit the requests are counted per, as a `date_trunc` field and
+    /// a `to_char` format.
+    fn period(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Hour | Self::Day => ("hour", "YYYY-MM-DD HH24:00 \"UTC\""),
+            Self::Week | Self::Month => ("day", "YYYY-MM-DD"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StatsQuery {
+    #[serde(default)]
+    pub window: Window,
+}
+
+/// The usage over a [`Window`].
+#[derive(Serialize)]
+pub struct Usage {
+    pub window: Window,
+    pub requests: i64,
+    /// The share of the forecasts served without asking the upstream.
+    pub hit_ratio: Option<f64>,
+    pub upstream_errors: i64,
+    pub top_cities: Vec<TopCity>,
+    /// The requests per hour, or per day over a week or longer.
+    pub periods: Vec<Period>,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct TopCity {
+    pub name: String,
+    pub requests: i64,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct Period {
+    pub start: String,
+    pub requests: i64,
+}
+
+impl Usage {
+    pub async fn new(pool: &PgPool, window: Window) -> Result<Self, sqlx::Error> {
+        let secs = window.duration().as_secs_f64();
+        let (requests, hit_ratio, upstream_errors): (i64, Option<f64>, i64) = sqlx::query_as(
+            "SELECT count(*), avg(cache_hit::int)::float8, count(*) FILTER (WHERE upstream_error) \
+            FROM queries WHERE requested_at > now() - make_interval(secs => $1)",
+        )
+        .bind(secs)
+        .fetch_one(pool)
+        .await?;
+
+        let top_cities = sqlx::query_as(
+            "SELECT coalesce(cities.canonical_name, cities.name) AS name, count(*) AS requests \
+            FROM queries JOIN cities ON cities.id = queries.city_id \
+            WHERE requested_at > now() - make_interval(secs => $1) \
+            GROUP BY cities.id ORDER BY requests DESC, name LIMIT $2",
+        )
+        .bind(secs)
+        .bind(TOP_CITIES)
+        .fetch_all(pool)
+        .await?;
+
+        let (unit, format) = window.period();
+        let periods = sqlx::query_as(&format!(
+            "SELECT to_char(date_trunc('{unit}', requested_at AT TIME ZONE 'UTC'), '{format}') \
+                AS start, count(*) AS requests \
+            FROM queries WHERE requested_at > now() - make_interval(secs => $1) \
+            GROUP BY 1 ORDER BY 1",
+        ))
+        .bind(secs)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(Self {
+            window,
+            requests,
+            hit_ratio,
+            upstream_errors,
+            top_cities,
+            periods,
+        })
+    }
+
+    /// The hit ratio as a rounded percentage.
+    pub fn hit_percentage(&self) -> Option<String> {
+        self.hit_ratio.map(|ratio| format!("{:.0}%", ratio * 100.))
+    }
+}
@@ -0,0 +1,1712 @@
+//! End-to-end tests of the router against a stub upstream and an ephemeral
+//! database. Set `FORECAST_TEST_DATABASE_URL` to a Postgres URL whose user
+//! may create databases to run them; otherwise they are skipped.
+
+use {
+    crate::{
+        config::{self, Cli, Config},
+        db, location,
+        provider::{Error::NoMatch, Fixtures, Geocoder, WeatherProvider, WeatherRequest},
+        router,
+        user::{self, Role},
+        variable::Variable,
+        App,
+    },
+    axum::{
+        body::Body,
+        extract::{ConnectInfo, Query, State},
+        http::{header, HeaderName, Request, StatusCode},
+        response::{IntoResponse, Response},
+        routing, Router, Server,
+    },
+    clap::Parser,
+    serde_json::{json, Value},
+    sqlx::{postgres::PgConnectOptions, Executor, PgPool},
+    std::{
+        collections::HashMap,
+        env,
+        net::{SocketAddr, TcpListener},
+        process,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    },
+    time::{macros::format_description, OffsetDateTime},
+    tower::ServiceExt,
+};
+
+const ADMIN_AUTH: &str = "Basic Zm9yZWNhc3Q6Zm9yZWNhc3Q="; // forecast:forecast
+
+/// Canned Open-Meteo responses with call counters.
+#[derive(Default)]
+struct Stub {
+    geocoding_calls: AtomicUsize,
+    forecast_calls: AtomicUsize,
+    /// Requests for the current conditions, which aren't forecast calls.
+    current_calls: AtomicUsize,
+    current_fails: AtomicBool,
+    forecast_fails: AtomicBool,
+    /// How many of the next forecast requests fail.
+    forecast_failures: AtomicUsize,
+    forecast_malformed: AtomicBool,
+    forecast_hangs: AtomicBool,
+    omit_daily: AtomicBool,
+    /// Whether the hourly series starts at the current hour, not a fixed day.
+    current_hours: AtomicBool,
+    slow: AtomicBool,
+    forecast_query: Mutex<HashMap<String, String>>,
+    geocoding_query: Mutex<HashMap<String, String>>,
+}
+
+impl Stub {
+    async fn delay(&self) {
+        if self.slow.load(Ordering::SeqCst) {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    fn serve(self: &Arc<Self>) -> String {
+        async fn search(
+            Query(query): Query<HashMap<String, String>>,
+            State(stub): State<Arc<Stub>>,
+        ) -> Response {
+            stub.geocoding_calls.fetch_add(1, Ordering::SeqCst);
+            *stub.geocoding_query.lock().expect("lock geocoding query") = query.clone();
+            stub.delay().await;
+            // Like Open-Meteo, match names regardless of case and diacritics
+            let name = query.get("name").map(String::as_str).unwrap_or_default();
+            let results: Vec<_> = places()
+                .into_iter()
+                .filter(|place| {
+                    let place = place["name"].as_str().unwrap_or_default();
+                    location::normalize(place) == location::normalize(name)
+                })
+                .collect();
+
+            if results.is_empty() {
+                return json_response(json!({ "generationtime_ms": 0.5 }));
+            }
+
+            json_response(json!({ "results": results }))
+        }
+
+        async fn get(
+            Query(query): Query<HashMap<String, String>>,
+            State(stub): State<Arc<Stub>>,
+        ) -> Response {
+            stub.geocoding_calls.fetch_add(1, Ordering::SeqCst);
+            let id: Option<i64> = query.get("id").and_then(|id| id.parse().ok());
+            match places().into_iter().find(|place| place["id"] == json!(id)) {
+                Some(place) => json_response(place),
+                None => StatusCode::BAD_REQUEST.into_response(),
+            }
+        }
+
+        async fn forecast(
+            Query(query): Query<HashMap<String, String>>,
+            State(stub): State<Arc<Stub>>,
+        ) -> Response {
+            // Like Open-Meteo, give the times in the requested time zone
+            let (timezone, offset, abbreviation) = match query.get("timezone").map(String::as_str) {
+                Some("Europe/London") => ("Europe/London", 3600, "BST"),
+                _ => ("GMT", 0, "GMT"),
+            };
+
+            if query.contains_key("current") {
+                stub.current_calls.fetch_add(1, Ordering::SeqCst);
+                if stub.current_fails.load(Ordering::SeqCst) {
+                    return StatusCode::SERVICE_UNAVAILABLE.into_response();
+                }
+
+                return json_response(json!({
+                    "utc_offset_seconds": offset,
+                    "timezone": timezone,
+                    "timezone_abbreviation": abbreviation,
+                    "current": {
+                        "time": "2023-10-01T00:45",
+                        "temperature_2m": 13.0,
+                        "apparent_temperature": 11.5,
+                        "relative_humidity_2m": 82.0,
+                        "wind_speed_10m": 14.4,
+                        "wind_direction_10m": 225.0,
+                        "weather_code": 61,
+                    },
+                }));
+            }
+
+            stub.forecast_calls.fetch_add(1, Ordering::SeqCst);
+            *stub.forecast_query.lock().expect("lock forecast query") = query.clone();
+            stub.delay().await;
+            let fail_next = stub.forecast_failures.fetch_update(
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+                |failures| failures.checked_sub(1),
+            );
+
+            if stub.forecast_fails.load(Ordering::SeqCst) || fail_next.is_ok() {
+                return StatusCode::SERVICE_UNAVAILABLE.into_response();
+            }
+
+            if stub.forecast_malformed.load(Ordering::SeqCst) {
+                return json_response(json!({ "hourly": "soon" }));
+            }
+
+            if stub.forecast_hangs.load(Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_secs(3)).await;
+            }
+
+            let time = if stub.current_hours.load(Ordering::SeqCst) {
+                let now = OffsetDateTime::now_utc() + time::Duration::seconds(offset);
+                let format = format_description!("[year]-[month]-[day]T[hour]:00");
+                let hour = |n| {
+                    (now + time::Duration::hours(n))
+                        .format(format)
+                        .expect("format hour")
+                };
+                json!([hour(0), hour(1)])
+            } else {
+                json!(["2023-10-01T00:00", "2023-10-01T01:00"])
+            };
+
+            let mut body = json!({
+                "utc_offset_seconds": offset,
+                "timezone": timezone,
+                "timezone_abbreviation": abbreviation,
+                "hourly": {
+                    "time": time,
+                    "temperature_2m": [12.5, 11.75],
+                    "weather_code": [3, 61],
+                },
+            });
+
+            let hourly = query.get("hourly").map(String::as_str).unwrap_or_default();
+            if hourly.split(',').any(|name| name == "precipitation") {
+                body["hourly"]["precipitation"] = json!([0.0, 0.4]);
+            }
+
+            if query.contains_key("daily") && !stub.omit_daily.load(Ordering::SeqCst) {
+                body["daily"] = json!({
+                    "time": ["2023-10-01"],
+                    "temperature_2m_min": [9.25],
+                    "temperature_2m_max": [15.5],
+                    "precipitation_sum": [1.5],
+                    "sunrise": ["2023-10-01T06:01"],
+                    "sunset": ["2023-10-01T17:42"],
+                    "weather_code": [61],
+                });
+            }
+
+            json_response(body)
+        }
+
+        let router = Router::new()
+            .route("/v1/search", routing::get(search))
+            .route("/v1/get", routing::get(get))
+            .route("/v1/forecast", routing::get(forecast))
+            .with_state(Arc::clone(self));
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind stub upstream");
+        let addr = listener.local_addr().expect("stub upstream address");
+        let server = Server::from_tcp(listener)
+            .expect("serve stub upstream")
+            .serve(router.into_make_service());
+
+        tokio::spawn(server);
+        format!("http://{addr}")
+    }
+}
+
+/// The places known to the stub geocoder.
+fn places() -> Vec<Value> {
+    vec![
+        json!({
+            "id": 2643743,
+            "name": "London",
+            "latitude": 51.50853,
+            "longitude": -0.12574,
+            "country": "United Kingdom",
+            "country_code": "GB",
+            "admin1": "England",
+            "timezone": "Europe/London",
+            "elevation": 25.0,
+            "population": 7556900,
+        }),
+        json!({
+            "id": 4409896,
+            "name": "Springfield",
+            "latitude": 37.21533,
+            "longitude": -93.29824,
+            "country": "United States",
+            "country_code": "US",
+            "admin1": "Missouri",
+            "timezone": "America/Chicago",
+            "elevation": 400.0,
+            "population": 166810,
+        }),
+        json!({
+            "id": 4250542,
+            "name": "Springfield",
+            "latitude": 39.80172,
+            "longitude": -89.64371,
+            "country": "United States",
+            "country_code": "US",
+            "admin1": "Illinois",
+            "timezone": "America/Chicago",
+            "elevation": 182.0,
+            "population": 116250,
+        }),
+        json!({
+            "id": 2657896,
+            "name": "Zürich",
+            "latitude": 47.36667,
+            "longitude": 8.55,
+            "country": "Switzerland",
+            "country_code": "CH",
+            "admin1": "Zurich",
+            "timezone": "Europe/Zurich",
+            "elevation": 408.0,
+            "population": 341730,
+        }),
+        json!({
+            "id": 3576022,
+            "name": "St. John's",
+            "latitude": 17.12096,
+            "longitude": -61.84329,
+            "country": "Antigua and Barbuda",
+            "country_code": "AG",
+            "admin1": "Saint John",
+            "timezone": "America/Antigua",
+            "elevation": 8.0,
+            "population": 24226,
+        }),
+    ]
+}
+
+fn json_response(value: Value) -> Response {
+    let content_type = [(header::CONTENT_TYPE, "application/json")];
+    (content_type, value.to_string()).into_response()
+}
+
+/// A database created for a single test and dropped by [`Harness::finish`].
+struct TestDb {
+    admin: PgPool,
+    name: String,
+    pool: PgPool,
+}
+
+impl TestDb {
+    async fn create() -> Option<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let Ok(url) = env::var("FORECAST_TEST_DATABASE_URL") else {
+            eprintln!("FORECAST_TEST_DATABASE_URL is not set, skipping");
+            return None;
+        };
+
+        let options: PgConnectOptions = url.parse().expect("parse test database url");
+        let admin = PgPool::connect_with(options.clone())
+            .await
+            .expect("connect to test database");
+
+        let name = format!(
+            "forecast_test_{}_{}",
+            process::id(),
+            NEXT.fetch_add(1, Ordering::SeqCst),
+        );
+
+        admin
+            .execute(format!("CREATE DATABASE {name}").as_str())
+            .await
+            .expect("create ephemeral database");
+
+        let pool = PgPool::connect_with(options.database(&name))
+            .await
+            .expect("connect to ephemeral database");
+
+        Some(Self { admin, name, pool })
+    }
+
+    async fn drop(self) {
+        self.pool.close().await;
+        let name = self.name;
+        self.admin
+            .execute(format!("DROP DATABASE {name} WITH (FORCE)").as_str())
+            .await
+            .expect("drop ephemeral database");
+    }
+}
+
+struct Harness {
+    router: Router,
+    stub: Arc<Stub>,
+    db: TestDb,
+}
+
+impl Harness {
+    async fn start() -> Option<Self> {
+        Self::start_with(|_| {}).await
+    }
+
+    async fn start_with<F>(configure: F) -> Option<Self>
+    where
+        F: FnOnce(&mut Config),
+    {
+        let db = TestDb::create().await?;
+        let stub = Arc::new(Stub::default());
+        let base = stub.serve();
+
+        let mut config = Config::default();
+        config.upstream.geocoding_url.clone_from(&base);
+        config.upstream.forecast_url = base;
+        // Retries would multiply the upstream calls the tests count
+        config.upstream.retries = 0;
+        configure(&mut config);
+
+        if let Err(err) = db::migrate(&db.pool).await {
+            panic!("migrate ephemeral database: {err}");
+        }
+
+        if let Err(err) = user::create(&db.pool, "forecast", "forecast", Role::Admin).await {
+            panic!("create admin user: {err}");
+        }
+
+        let app = match App::new(db.pool.clone(), config).await {
+            Ok(app) => app,
+            Err(err) => panic!("set up application: {err}"),
+        };
+
+        Some(Self {
+            router: router(app),
+            stub,
+            db,
+        })
+    }
+
+    async fn get(&self, uri: &str, auth: Option<&str>) -> (StatusCode, Response<String>) {
+        match auth {
+            Some(auth) => self.get_with(uri, &[(header::AUTHORIZATION, auth)]).await,
+            None => self.get_with(uri, &[]).await,
+        }
+    }
+
+    async fn get_with(
+        &self,
+        uri: &str,
+        headers: &[(HeaderName, &str)],
+    ) -> (StatusCode, Response<String>) {
+        let mut req = Request::get(uri);
+        for (name, value) in headers {
+            req = req.header(name, *value);
+        }
+
+        self.send(req.body(Body::empty()).expect("build request"))
+            .await
+    }
+
+    async fn post_form(
+        &self,
+        uri: &str,
+        form: &[(&str, &str)],
+        cookie: &str,
+    ) -> (StatusCode, Response<String>) {
+        let form = serde_urlencoded::to_string(form).expect("encode form");
+        let req = Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(header::COOKIE, cookie)
+            .body(Body::from(form))
+            .expect("build request");
+
+        self.send(req).await
+    }
+
+    /// Signs in as the admin user and returns the session cookie.
+    async fn login(&self) -> String {
+        let (_, res) = self.get("/login", None).await;
+        let csrf = field(&res, "csrf");
+        let form = [
+            ("name", "forecast"),
+            ("password", "forecast"),
+            ("csrf", &csrf),
+        ];
+        let (status, res) = self
+            .post_form("/login", &form, &cookie(&res, "forecast_csrf"))
+            .await;
+
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        cookie(&res, "forecast_session")
+    }
+
+    async fn send(&self, req: Request<Body>) -> (StatusCode, Response<String>) {
+        let res = self
+            .router
+            .clone()
+            .oneshot(req)
+            .await
+            .expect("send request");
+
+        let (parts, body) = res.into_parts();
+        let body = hyper::body::to_bytes(body).await.expect("read body");
+        let body = String::from_utf8(body.to_vec()).expect("utf-8 body");
+        (parts.status, Response::from_parts(parts, body))
+    }
+
+    async fn cities(&self) -> i64 {
+        sqlx::query_scalar("SELECT count(*) FROM cities")
+            .fetch_one(&self.db.pool)
+            .await
+            .expect("count cities")
+    }
+
+    fn geocoding_calls(&self) -> usize {
+        self.stub.geocoding_calls.load(Ordering::SeqCst)
+    }
+
+    fn forecast_calls(&self) -> usize {
+        self.stub.forecast_calls.load(Ordering::SeqCst)
+    }
+
+    fn forecast_param(&self, key: &str) -> String {
+        let query = self
+            .stub
+            .forecast_query
+            .lock()
+            .expect("lock forecast query");
+        query.get(key).cloned().unwrap_or_default()
+    }
+
+    fn geocoding_param(&self, key: &str) -> String {
+        let query = self
+            .stub
+            .geocoding_query
+            .lock()
+            .expect("lock geocoding query");
+        query.get(key).cloned().unwrap_or_default()
+    }
+
+    async fn finish(self) {
+        self.db.drop().await;
+    }
+}
+
+/// Returns the `name=value` pair of a cookie set by the response.
+fn cookie(res: &Response<String>, name: &str) -> String {
+    let set = res.headers().get_all(header::SET_COOKIE).iter();
+    let cookie = set
+        .filter_map(|value| value.to_str().ok())
+        .find(|value| value.starts_with(&format!("{name}=")))
+        .expect("cookie is set");
+
+    cookie.split(';').next().unwrap_or_default().to_owned()
+}
+
+/// Returns the value of a form field rendered by the response.
+fn field(res: &Response<String>, name: &str) -> String {
+    let prefix = format!("name=\"{name}\" value=\"");
+    let (_, rest) = res.body().split_once(&prefix).expect("form field");
+    rest.split('"').next().unwrap_or_default().to_owned()
+}
+
+#[tokio::test]
+async fn fixtures_provider() {
+    let dir = env::temp_dir().join(format!("forecast-fixtures-{}", process::id()));
+    let geocoding = dir.join("geocoding");
+    std::fs::create_dir_all(&geocoding).expect("create fixtures directory");
+    let london = json!({ "results": [places()[0]] });
+    std::fs::write(geocoding.join("london.json"), london.to_string()).expect("write fixture");
+    std::fs::write(geocoding.join("README"), "Recorded responses").expect("write readme");
+    std::fs::write(dir.join("secret.json"), london.to_string()).expect("write fixture");
+    let forecast = json!({
+        "utc_offset_seconds": 0,
+        "hourly": { "time": ["2023-10-01T00:00"], "temperature_2m": [12.5] },
+    });
+    std::fs::write(dir.join("forecast.json"), forecast.to_string()).expect("write fixture");
+
+    let fixtures = Fixtures::new(dir.clone());
+    let results = fixtures.search("London").await.unwrap_or_default();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].name, "London");
+    assert!(matches!(fixtures.search("Atlantis").await, Err(NoMatch)));
+    assert!(matches!(fixtures.search("../secret").await, Err(NoMatch)));
+
+    // Stray files next to the recorded responses are skipped
+    let place = fixtures.place(2643743).await.ok().map(|place| place.name);
+    assert_eq!(place.as_deref(), Some("London"));
+    assert!(matches!(fixtures.place(1).await, Err(NoMatch)));
+
+    let req = WeatherRequest {
+        ll: results[0].ll,
+        hourly: vec![Variable::Temperature],
+        daily: false,
+        current: false,
+        timezone: None,
+    };
+
+    let weather = fixtures.weather(&req).await.ok().map(|res| res.hourly);
+    assert_eq!(
+        weather.map(|hourly| hourly.temperature_2m),
+        Some(vec![Some(12.5)])
+    );
+
+    std::fs::remove_dir_all(&dir).expect("remove fixtures directory");
+}
+
+#[tokio::test]
+async fn startup_requires_migrations() {
+    let Some(db) = TestDb::create().await else {
+        return;
+    };
+
+    let Err(err) = App::new(db.pool.clone(), Config::default()).await else {
+        panic!("started without migrations");
+    };
+
+    assert!(err.to_string().contains("run `forecast migrate` first"));
+
+    if let Err(err) = db::migrate(&db.pool).await {
+        panic!("migrate ephemeral database: {err}");
+    }
+
+    assert!(App::new(db.pool.clone(), Config::default()).await.is_ok());
+    db.drop().await;
+}
+
+#[test]
+fn config_precedence() {
+    let path = env::temp_dir().join(format!("forecast-{}.toml", process::id()));
+    std::fs::write(&path, "[cache]\nmemory_capacity = 1\n").expect("write config");
+    let path = path.to_str().expect("utf-8 path");
+
+    // The config file is overridden by the environment, and that by flags
+    let capacity = |flags: &[&str]| {
+        let args = ["forecast", "--config", path]
+            .into_iter()
+            .chain(flags.iter().copied());
+        match Config::load(&Cli::parse_from(args)) {
+            Ok(config) => config.cache.memory_capacity,
+            Err(err) => panic!("load config: {err}"),
+        }
+    };
+
+    assert_eq!(capacity(&[]), 1);
+    env::set_var("FORECAST_MEMORY_CAPACITY", "2");
+    assert_eq!(capacity(&[]), 2);
+    assert_eq!(capacity(&["--memory-capacity", "3"]), 3);
+    env::remove_var("FORECAST_MEMORY_CAPACITY");
+    std::fs::remove_file(path).expect("remove config");
+}
+
+#[test]
+fn config_rejects_long_ttls() {
+    let cli = Cli::parse_from([
+        "forecast",
+        "--config",
+        "forecast.example.toml",
+        "--memory-ttl",
+        "99999999999",
+    ]);
+
+    let Err(err) = Config::load(&cli) else {
+        panic!("accepted a memory TTL of thousands of years");
+    };
+
+    assert!(err
+        .to_string()
+        .contains("cache.memory_ttl_secs must be at most"));
+}
+
+#[tokio::test]
+async fn weather_caches_lookups() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    let (status, res) = h.get("/weather?city=London", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("Weather for London"));
+    assert!(res.body().contains("12.5"));
+    assert_eq!(h.geocoding_calls(), 1);
+    assert_eq!(h.forecast_calls(), 1);
+    assert_eq!(h.cities().await, 1);
+
+    let (status, _) = h.get("/weather?city=London", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(h.geocoding_calls(), 1);
+    assert_eq!(h.forecast_calls(), 1);
+    assert_eq!(h.cities().await, 1);
+
+    // A different variable set is cached separately
+    let (status, _) = h
+        .get("/weather?city=London&variables=temperature", None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(h.forecast_calls(), 2);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn weather_coalesces_concurrent_lookups() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    h.stub.slow.store(true, Ordering::SeqCst);
+    let uri = "/weather?city=London";
+    let responses = tokio::join!(
+        h.get(uri, None),
+        h.get(uri, None),
+        h.get(uri, None),
+        h.get(uri, None),
+    );
+
+    for (status, _) in [responses.0, responses.1, responses.2, responses.3] {
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    assert_eq!(h.geocoding_calls(), 1);
+    assert_eq!(h.forecast_calls(), 1);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn weather_serves_stale_forecast() {
+    let Some(h) = Harness::start_with(|config| config.cache.forecast_ttl_secs = 0).await else {
+        return;
+    };
+
+    let (status, res) = h.get("/weather?city=London", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!res.body().contains("Stale"));
+
+    // Past its TTL the forecast is refetched
+    let (status, _) = h.get("/weather?city=London", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(h.forecast_calls(), 2);
+
+    h.stub.forecast_fails.store(true, Ordering::SeqCst);
+    let (status, res) = h.get("/weather?city=London", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("Stale"));
+    assert!(res.body().contains("12.5"));
+    assert_eq!(h.forecast_calls(), 3);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn weather_cache_expires_by_fetch_time() {
+    let Some(h) = Harness::start_with(|config| config.cache.forecast_ttl_secs = 2).await else {
+        return;
+    };
+
+    // A forecast fetched by an earlier process, most of its TTL ago
+    let cached = json!({
+        "hourly": { "time": ["2023-10-01T00:00"], "temperature_2m": [20.5] },
+    });
+
+    sqlx::query(
+        "INSERT INTO forecasts (lat, lng, variables, response, fetched_at) \
+        VALUES (10, 20, 'temperature,timezone=auto', $1, now() - interval '1.5 seconds')",
+    )
+    .bind(cached)
+    .execute(&h.db.pool)
+    .await
+    .expect("insert cached forecast");
+
+    let uri = "/api/v1/weather?lat=10&lng=20&variables=temperature";
+    let (status, res) = h.get(uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: Value = serde_json::from_str(res.body()).expect("parse json body");
+    assert_eq!(body["hourly"]["temperature_2m"][0], 20.5);
+    assert_eq!(h.forecast_calls(), 0);
+
+    // It's kept in memory only for what was left of its TTL
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let (status, res) = h.get(uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: Value = serde_json::from_str(res.body()).expect("parse json body");
+    assert_eq!(body["hourly"]["temperature_2m"][0], 12.5);
+    assert_eq!(h.forecast_calls(), 1);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn weather_variables() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    let uri = "/weather?city=London&variables=temperature,weather_code";
+    let (status, res) = h.get(uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("Overcast"));
+    assert!(res.body().contains("Slight rain"));
+    assert!(!res.body().contains("<th>Humidity</th>"));
+
+    assert_eq!(h.forecast_param("hourly"), "temperature_2m,weather_code");
+
+    let (status, res) = h.get("/weather?city=London&variables=snow", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(res.body().contains("unknown variable \"snow\""));
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn weather_daily() {
+    let Some(h) = Harness::start_with(|config| config.cache.forecast_ttl_secs = 0).await else {
+        return;
+    };
+
+    let (status, res) = h.get("/weather?city=London&view=daily", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("Daily weather for London"));
+    assert!(res.body().contains("15.5"));
+    assert!(res.body().contains("06:01"));
+    assert!(h.forecast_param("daily").contains("temperature_2m_max"));
+    assert_eq!(h.forecast_param("hourly"), "");
+
+    // Without a daily summary upstream it's aggregated from the hourly series
+    h.stub.omit_daily.store(true, Ordering::SeqCst);
+    let (status, res) = h.get("/weather?city=London&view=daily", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("<td>11.75°C</td>"));
+    assert!(res.body().contains("<td>12.5°C</td>"));
+    assert!(res.body().contains("Slight rain"));
+
+    let (status, _) = h.get("/weather?city=London&view=weekly", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn weather_unknown_city() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    let (status, _) = h.get("/weather?city=Atlantis", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(h.forecast_calls(), 0);
+    assert_eq!(h.cities().await, 0);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn weather_upstream_failure() {
+    let Some(h) = Harness::start_with(|config| config.upstream.timeout_secs = 1).await else {
+        return;
+    };
+
+    let error = |res: &Response<String>| -> Value {
+        let body: Value = serde_json::from_str(res.body()).expect("parse json body");
+        body["error"].clone()
+    };
+
+    let accept = [(header::ACCEPT, "application/json")];
+    h.stub.forecast_fails.store(true, Ordering::SeqCst);
+    let (status, res) = h.get_with("/weather?city=London", &accept).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(error(&res)["code"], "upstream_status");
+    assert_eq!(
+        error(&res)["message"],
+        "the forecast service responded with 503",
+    );
+
+    h.stub.forecast_fails.store(false, Ordering::SeqCst);
+    h.stub.forecast_malformed.store(true, Ordering::SeqCst);
+    let (status, res) = h.get_with("/weather?city=London", &accept).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(error(&res)["code"], "upstream_payload");
+
+    h.stub.forecast_malformed.store(false, Ordering::SeqCst);
+    h.stub.forecast_hangs.store(true, Ordering::SeqCst);
+    let (status, res) = h.get_with("/weather?city=London", &accept).await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(error(&res)["code"], "upstream_timeout");
+    assert_eq!(h.forecast_calls(), 3);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn stats_requires_auth() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    let (status, res) = h.get("/stats", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(res.headers().contains_key(header::WWW_AUTHENTICATE));
+
+    let wrong = "Basic Zm9yZWNhc3Q6d3Jvbmc="; // forecast:wrong
+    let (status, _) = h.get("/stats", Some(wrong)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    h.get("/weather?city=London", None).await;
+    let (status, res) = h.get("/stats", Some(ADMIN_AUTH)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("London"));
+    assert!(res.body().contains("Signed in as forecast (admin)"));
+
+    let disable = user::set_disabled(&h.db.pool, "forecast", true).await;
+    assert!(disable.is_ok());
+    let (status, _) = h.get("/stats", Some(ADMIN_AUTH)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let missing = "Basic bWlzc2luZzpmb3JlY2FzdA=="; // missing:forecast
+    let (status, _) = h.get("/stats", Some(missing)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn json_api() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    let json = |res: &Response<String>| -> Value {
+        let content_type = &res.headers()[header::CONTENT_TYPE];
+        assert_eq!(content_type, "application/json");
+        serde_json::from_str(res.body()).expect("parse json body")
+    };
+
+    let uri = "/api/v1/weather?city=London&variables=temperature";
+    let (status, res) = h.get(uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let body = json(&res);
+    assert_eq!(body["city"], "London");
+    assert_eq!(body["view"], "hourly");
+    assert_eq!(body["hourly"]["temperature_2m"][0], 12.5);
+    assert!(body["hourly"].get("precipitation").is_none());
+
+    // The pages negotiate the format with the `Accept` header
+    let accept = [(header::ACCEPT, "application/json, text/html;q=0.9")];
+    let (status, res) = h.get_with("/weather?city=London&view=daily", &accept).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json(&res)["daily"]["temperature_2m_max"][0], 15.5);
+
+    let (status, res) = h.get_with("/weather?city=Atlantis", &accept).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(json(&res)["error"]["message"], "no results found");
+
+    let (status, res) = h.get("/api/v1/weather", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json(&res)["error"]["status"], 400);
+
+    let (status, res) = h.get("/api/v1/stats", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(json(&res)["error"]["message"], "unauthorized");
+
+    let (status, res) = h.get("/api/v1/stats", Some(ADMIN_AUTH)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json(&res)["cities"][0]["name"], "London");
+
+    let (status, res) = h.get("/api/v1/cities", Some(ADMIN_AUTH)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json(&res)["cities"][0]["latitude"], 51.50853);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn login_sessions() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    // Browsers are sent to the login page, other clients get a challenge
+    let accept = [(header::ACCEPT, "text/html,application/xhtml+xml,*/*;q=0.8")];
+    let (status, res) = h.get_with("/stats", &accept).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    assert_eq!(res.headers()[header::LOCATION], "/login?next=%2Fstats");
+
+    let (status, res) = h.get("/login?next=/stats", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let csrf_cookie = cookie(&res, "forecast_csrf");
+    let csrf = field(&res, "csrf");
+    assert_eq!(field(&res, "next"), "/stats");
+
+    let mut form = vec![
+        ("name", "forecast"),
+        ("password", "forecast"),
+        ("csrf", "forged"),
+        ("next", "/stats"),
+    ];
+
+    let (status, _) = h.post_form("/login", &form, &csrf_cookie).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    form[2].1 = &csrf;
+    form[1].1 = "wrong";
+    let (status, res) = h.post_form("/login", &form, &csrf_cookie).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(res.body().contains("Invalid name or password"));
+
+    form[1].1 = "forecast";
+    let (status, res) = h.post_form("/login", &form, &csrf_cookie).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    assert_eq!(res.headers()[header::LOCATION], "/stats");
+    let session = cookie(&res, "forecast_session");
+
+    // Browsers drop tabs and newlines, so those targets aren't followed
+    for next in [
+        "/\t/example.com",
+        "/\nboom",
+        "//example.com",
+        "https://example.com",
+    ] {
+        form[3].1 = next;
+        let (status, res) = h.post_form("/login", &form, &csrf_cookie).await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        assert_eq!(res.headers()[header::LOCATION], "/stats");
+    }
+
+    let (status, res) = h.get("/login?next=/%09/example.com", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(field(&res, "next"), "/stats");
+
+    let (status, res) = h.get_with("/stats", &[(header::COOKIE, &session)]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("Signed in as forecast (admin)"));
+    let logout_csrf = field(&res, "csrf");
+
+    // A tampered cookie isn't accepted
+    let tampered = format!("{session}x");
+    let (status, _) = h.get_with("/stats", &[(header::COOKIE, &tampered)]).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = h
+        .post_form("/logout", &[("csrf", "forged")], &session)
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = h
+        .post_form("/logout", &[("csrf", &logout_csrf)], &session)
+        .await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+
+    let (status, _) = h.get_with("/stats", &[(header::COOKIE, &session)]).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn api_keys() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    // The forms need the CSRF token of a login session
+    let (status, _) = h.get("/admin/keys", Some(ADMIN_AUTH)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let session = h.login().await;
+    let (status, res) = h
+        .get_with("/admin/keys", &[(header::COOKIE, &session)])
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let csrf = field(&res, "csrf");
+
+    for days in ["0", "3651", "99999999"] {
+        let form = [
+            ("name", "dashboard"),
+            ("stats:read", "on"),
+            ("expires_in_days", days),
+            ("csrf", &csrf),
+        ];
+        let (status, res) = h.post_form("/admin/keys", &form, &session).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(res
+            .body()
+            .contains("expires_in_days must be from 1 to 3650 days"));
+    }
+
+    let form = [("name", "dashboard"), ("stats:read", "on"), ("csrf", &csrf)];
+    let (status, res) = h.post_form("/admin/keys", &form, &session).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, rest) = res.body().split_once("<pre>").expect("issued key");
+    let key = rest.split('<').next().unwrap_or_default().to_owned();
+    assert!(key.starts_with("fk_"));
+
+    let stored: String = sqlx::query_scalar("SELECT key_hash FROM api_keys")
+        .fetch_one(&h.db.pool)
+        .await
+        .expect("query key hash");
+    assert_ne!(stored, key);
+
+    let bearer = format!("Bearer {key}");
+    let (status, res) = h.get("/api/v1/stats", Some(&bearer)).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: Value = serde_json::from_str(res.body()).expect("parse json body");
+    assert_eq!(body["key"]["name"], "dashboard");
+    assert_eq!(body["key"]["scopes"], json!(["stats:read"]));
+
+    let used: bool = sqlx::query_scalar("SELECT last_used_at IS NOT NULL FROM api_keys")
+        .fetch_one(&h.db.pool)
+        .await
+        .expect("query last use");
+    assert!(used);
+
+    // The key wasn't granted `weather:read`, though the route is public
+    let (status, _) = h.get("/api/v1/weather?city=London", Some(&bearer)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = h.get("/api/v1/weather?city=London", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = h.get("/api/v1/stats", Some("Bearer fk_unknown")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let id: i32 = sqlx::query_scalar("SELECT id FROM api_keys")
+        .fetch_one(&h.db.pool)
+        .await
+        .expect("query key id");
+
+    let uri = format!("/admin/keys/{id}/revoke");
+    let (status, _) = h.post_form(&uri, &[("csrf", "forged")], &session).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = h.post_form(&uri, &[("csrf", &csrf)], &session).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+
+    let (status, _) = h.get("/api/v1/stats", Some(&bearer)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn admin_requires_role() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    if let Err(err) = user::create(&h.db.pool, "viewer", "viewer-password", Role::Viewer).await {
+        panic!("create viewer user: {err}");
+    }
+
+    let viewer = "Basic dmlld2VyOnZpZXdlci1wYXNzd29yZA=="; // viewer:viewer-password
+    let (status, _) = h.get("/stats", Some(viewer)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Known callers without the role are forbidden rather than challenged
+    let (status, res) = h.get("/admin/keys", Some(viewer)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(!res.headers().contains_key(header::WWW_AUTHENTICATE));
+    assert!(res.body().contains("requires the admin role"));
+
+    let (status, _) = h.get("/admin/keys", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn weather_rate_limit() {
+    let limit = config::Limit {
+        burst: 2,
+        per_minute: 1,
+    };
+
+    let configure = |config: &mut Config| {
+        let routes = &mut config.rate_limit.routes;
+        routes.insert("/weather".to_owned(), limit);
+    };
+
+    let Some(h) = Harness::start_with(configure).await else {
+        return;
+    };
+
+    let get_from = |addr: [u8; 4]| {
+        let req = Request::get("/weather?city=London")
+            .extension(ConnectInfo(SocketAddr::from((addr, 40000))))
+            .body(Body::empty())
+            .expect("build request");
+
+        h.send(req)
+    };
+
+    for _ in 0..2 {
+        let (status, _) = get_from([192, 0, 2, 1]).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let (status, res) = get_from([192, 0, 2, 1]).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers()[header::RETRY_AFTER], "60");
+
+    // Made up keys don't get buckets of their own
+    for n in 0..3 {
+        let req = Request::get("/weather?city=London")
+            .header(header::AUTHORIZATION, format!("Bearer fk_unknown{n}"))
+            .extension(ConnectInfo(SocketAddr::from(([192, 0, 2, 3], 40000))))
+            .body(Body::empty())
+            .expect("build request");
+
+        let (status, _) = h.send(req).await;
+        match n {
+            0 | 1 => assert_eq!(status, StatusCode::UNAUTHORIZED),
+            _ => assert_eq!(status, StatusCode::TOO_MANY_REQUESTS),
+        }
+    }
+
+    // Other clients and routes have buckets of their own
+    let (status, _) = get_from([192, 0, 2, 2]).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = h.get("/stats", Some(ADMIN_AUTH)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let accept = [(header::ACCEPT, "application/json")];
+    h.get_with("/weather?city=London", &accept).await;
+    h.get_with("/weather?city=London", &accept).await;
+    let (status, res) = h.get_with("/weather?city=London", &accept).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    let body: Value = serde_json::from_str(res.body()).expect("parse json body");
+    assert_eq!(body["error"]["code"], "rate_limited");
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn upstream_retries_and_breaker() {
+    let configure = |config: &mut Config| {
+        config.upstream.retries = 2;
+        config.upstream.retry_backoff_ms = 1;
+        config.upstream.breaker_threshold = 3;
+    };
+
+    let Some(h) = Harness::start_with(configure).await else {
+        return;
+    };
+
+    // Transient failures are retried
+    h.stub.forecast_failures.store(2, Ordering::SeqCst);
+    let (status, _) = h.get("/weather?city=London", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(h.forecast_calls(), 3);
+
+    // Once the retries reach the threshold, requests fail fast
+    h.stub.forecast_fails.store(true, Ordering::SeqCst);
+    let accept = [(header::ACCEPT, "application/json")];
+    let uri = "/weather?city=London&variables=temperature";
+    let (status, _) = h.get_with(uri, &accept).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(h.forecast_calls(), 6);
+
+    let uri = "/weather?city=London&variables=relative_humidity";
+    let (status, res) = h.get_with(uri, &accept).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(res.body().contains("upstream_circuit_open"));
+    assert_eq!(h.forecast_calls(), 6);
+
+    let (_, res) = h.get("/api/v1/stats", Some(ADMIN_AUTH)).await;
+    let body: Value = serde_json::from_str(res.body()).expect("parse json body");
+    assert_eq!(body["circuits"]["forecast_open"], true);
+    assert_eq!(body["circuits"]["geocoding_open"], false);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn weather_disambiguation() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    let uri = "/weather?city=Springfield&view=daily";
+    let (status, res) = h.get(uri, None).await;
+    assert_eq!(status, StatusCode::MULTIPLE_CHOICES);
+    assert!(res.body().contains("Springfield, Illinois, United States"));
+    assert!(res.body().contains("/weather?place=4250542&amp;view=daily"));
+
+    // Ambiguous names aren't bound to a place
+    assert_eq!(h.cities().await, 0);
+
+    let accept = [(header::ACCEPT, "application/json")];
+    let (status, res) = h.get_with(uri, &accept).await;
+    assert_eq!(status, StatusCode::MULTIPLE_CHOICES);
+    let body: Value = serde_json::from_str(res.body()).expect("parse json body");
+    assert_eq!(body["candidates"][0]["admin1"], "Missouri");
+    assert_eq!(body["candidates"][1]["id"], 4250542);
+
+    let (status, res) = h.get("/weather?place=4250542&view=daily", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("Daily weather for Springfield</h1>"));
+    assert!(res.body().contains("Illinois, United States (US)"));
+    assert!(res.body().contains("/weather?place=4250542"));
+    assert_eq!(h.forecast_param("latitude"), "39.80172");
+
+    let (status, _) = h.get("/weather?place=1", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let geocoding_calls = h.geocoding_calls();
+    let (status, res) = h.get("/weather?lat=48.85&lng=2.35", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("Weather for 48.85, 2.35"));
+    assert_eq!(h.geocoding_calls(), geocoding_calls);
+
+    for uri in [
+        "/weather?city=London&lat=1&lng=2",
+        "/weather?lat=48.85",
+        "/weather?lat=91&lng=0",
+    ] {
+        let (status, _) = h.get(uri, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+    }
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn weather_place_metadata() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    let (status, res) = h.get("/weather?city=London", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("England, United Kingdom (GB)"));
+    assert!(res.body().contains("Europe/London"));
+    assert!(res.body().contains("population 7556900"));
+
+    let row: (Option<i64>, Option<String>, Option<String>, Option<f64>) = sqlx::query_as(
+        "SELECT place_id, canonical_name, timezone, elevation FROM cities WHERE name = 'London'",
+    )
+    .fetch_one(&h.db.pool)
+    .await
+    .expect("select city");
+    assert_eq!(
+        row,
+        (
+            Some(2643743),
+            Some("London".to_owned()),
+            Some("Europe/London".to_owned()),
+            Some(25.)
+        )
+    );
+
+    // A stored place is looked up by id without the geocoder
+    let accept = [(header::ACCEPT, "application/json")];
+    let (status, res) = h.get_with("/weather?place=2643743", &accept).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: Value = serde_json::from_str(res.body()).expect("parse json body");
+    assert_eq!(body["place"]["country_code"], "GB");
+    assert_eq!(h.geocoding_calls(), 1);
+
+    // The upstream id is stored once, whatever name it was found by
+    let (status, _) = h.get("/weather?place=4409896", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = h.get("/weather?place=4409896&view=daily", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(h.cities().await, 2);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn weather_normalized_lookup() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    for uri in [
+        "/weather?city=London",
+        "/weather?city=london",
+        "/weather?city=%20%20LONDON%20",
+    ] {
+        let (status, res) = h.get(uri, None).await;
+        assert_eq!(status, StatusCode::OK, "{uri}");
+        assert!(res.body().contains("Weather for London"), "{uri}");
+    }
+
+    assert_eq!(h.geocoding_calls(), 1);
+    assert_eq!(h.cities().await, 1);
+
+    let (status, res) = h.get("/weather?city=zurich", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("Weather for Zürich"));
+    let (status, _) = h.get("/weather?city=Z%C3%BCrich", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(h.geocoding_calls(), 2);
+    assert_eq!(h.cities().await, 2);
+
+    // Rows stored before the aliases, with a spelling of a stored city
+    sqlx::query(
+        "INSERT INTO cities (name, lat, lng) \
+        VALUES ('LONDON', 51.5, -0.13), ('Bern', 46.95, 7.45), ('bern ', 46.95, 7.45)",
+    )
+    .execute(&h.db.pool)
+    .await
+    .expect("insert cities");
+
+    sqlx::query(
+        "INSERT INTO queries (city_id, cache_hit, upstream_error, status, latency_ms, client) \
+        SELECT id, false, false, 200, 1, 'unknown' FROM cities \
+        WHERE name IN ('LONDON', 'Bern', 'bern ')",
+    )
+    .execute(&h.db.pool)
+    .await
+    .expect("insert queries");
+
+    let cleanup = location::cleanup(&h.db.pool)
+        .await
+        .expect("clean up cities");
+    assert_eq!((cleanup.aliases, cleanup.merged), (1, 2));
+    assert_eq!(h.cities().await, 3);
+
+    let aliases: Vec<(String, String)> = sqlx::query_as(
+        "SELECT alias, name FROM city_aliases JOIN cities ON cities.id = city_id ORDER BY alias",
+    )
+    .fetch_all(&h.db.pool)
+    .await
+    .expect("select aliases");
+    let expected = [("bern", "Bern"), ("london", "London"), ("zurich", "Zürich")];
+    let expected: Vec<_> = expected
+        .into_iter()
+        .map(|(alias, name)| (alias.to_owned(), name.to_owned()))
+        .collect();
+    assert_eq!(aliases, expected);
+
+    // The queries of merged rows count for the city they were merged into
+    let accept = [
+        (header::ACCEPT, "application/json"),
+        (header::AUTHORIZATION, ADMIN_AUTH),
+    ];
+    let (status, res) = h.get_with("/stats?window=hour", &accept).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: Value = serde_json::from_str(res.body()).expect("parse json body");
+    assert_eq!(
+        body["usage"]["top_cities"],
+        json!([
+            { "name": "London", "requests": 4 },
+            { "name": "Bern", "requests": 2 },
+            { "name": "Zürich", "requests": 2 },
+        ])
+    );
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn weather_query_validation() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    // The name is sent as a single, encoded parameter
+    let (status, res) = h.get("/weather?city=St.%20John%27s", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("Weather for St. John&#x27;s"));
+    assert_eq!(h.geocoding_param("name"), "St. John's");
+    assert_eq!(h.geocoding_param("count"), "10");
+
+    let long = format!("/weather?city={}", "a".repeat(101));
+    let variables = format!("/weather?city=London&variables={}", ",".repeat(257));
+    for (uri, message) in [
+        ("/weather?city=%20%20", "city must not be empty"),
+        (long.as_str(), "at most 100 characters"),
+        ("/weather?city=London%26count%3D100", "must not contain '&'"),
+        ("/weather?city=London%23x", "must not contain '#'"),
+        ("/weather?place=0", "place must be a positive id"),
+        (variables.as_str(), "variables must be at most 256 bytes"),
+    ] {
+        let (status, res) = h.get(uri, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+        assert!(res.body().contains(message), "{uri}: {}", res.body());
+    }
+
+    assert_eq!(h.geocoding_calls(), 1);
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn stats_query_log() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    for uri in [
+        "/weather?city=London",
+        "/weather?city=London",
+        "/weather?city=Zurich",
+        "/weather?city=Atlantis",
+    ] {
+        h.get(uri, None).await;
+    }
+
+    h.stub.forecast_fails.store(true, Ordering::SeqCst);
+    let (status, _) = h.get("/weather?city=London&view=daily", None).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+
+    let logged: Vec<(Option<String>, Option<bool>, bool, i16)> = sqlx::query_as(
+        "SELECT cities.name, cache_hit, upstream_error, status \
+        FROM queries LEFT JOIN cities ON cities.id = city_id ORDER BY queries.id",
+    )
+    .fetch_all(&h.db.pool)
+    .await
+    .expect("select queries");
+
+    let london = Some("London".to_owned());
+    assert_eq!(
+        logged,
+        [
+            (london.clone(), Some(false), false, 200),
+            (london.clone(), Some(true), false, 200),
+            (Some("Zürich".to_owned()), Some(false), false, 200),
+            (None, None, false, 404),
+            (london, None, true, 502),
+        ]
+    );
+
+    // Without a connection address the client is only known by its credentials
+    let (status, res) = h.get("/weather?city=London", Some(ADMIN_AUTH)).await;
+    assert_eq!(status, StatusCode::OK, "{}", res.body());
+    let clients: Vec<String> = sqlx::query_scalar("SELECT DISTINCT client FROM queries ORDER BY 1")
+        .fetch_all(&h.db.pool)
+        .await
+        .expect("select clients");
+    assert_eq!(clients, ["unknown", "user:forecast"]);
+
+    let accept = [
+        (header::ACCEPT, "application/json"),
+        (header::AUTHORIZATION, ADMIN_AUTH),
+    ];
+    let (status, res) = h.get_with("/stats?window=hour", &accept).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: Value = serde_json::from_str(res.body()).expect("parse json body");
+    let usage = &body["usage"];
+    assert_eq!(usage["window"], "hour");
+    assert_eq!(usage["requests"], 6);
+    assert_eq!(usage["upstream_errors"], 1);
+    assert_eq!(usage["hit_ratio"].as_f64(), Some(0.5));
+    assert_eq!(
+        usage["top_cities"][0],
+        json!({"name": "London", "requests": 4})
+    );
+    let periods = usage["periods"].as_array().expect("periods");
+    let per_period = periods.iter().map(|period| period["requests"].as_i64());
+    assert_eq!(per_period.sum::<Option<i64>>(), Some(6));
+
+    let (status, res) = h.get("/stats?window=week", Some(ADMIN_AUTH)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("Requests per day"));
+    assert!(res.body().contains("50% served from the cache"));
+
+    let (status, _) = h.get("/stats?window=year", Some(ADMIN_AUTH)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn weather_chart() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    let (status, res) = h.get("/weather/chart.svg?city=London", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(res.headers()[header::CONTENT_TYPE], "image/svg+xml");
+    assert_eq!(h.forecast_param("hourly"), "temperature_2m,precipitation");
+
+    let svg = res.body();
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.contains("<path d=\"M"));
+    assert!(svg.contains("<title>0.4 mm</title>"));
+    assert!(svg.contains(">max 12.5°C</text>"));
+    assert!(svg.contains(">min 11.75°C</text>"));
+    assert!(svg.contains(">2023-10-01</text>"));
+
+    let (status, res) = h.get("/weather?city=London", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("<figure class=\"chart\"><svg"));
+    assert!(res.body().contains("/weather/chart.svg?city=London"));
+
+    let (status, res) = h
+        .get("/weather?city=London&variables=cloud_cover", None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!res.body().contains("<svg"));
+
+    let (status, _) = h.get("/weather/chart.svg?city=Springfield", None).await;
+    assert_eq!(status, StatusCode::MULTIPLE_CHOICES);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn weather_units() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    let (status, res) = h.get("/weather?city=London", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("12.5°C"));
+    assert!(!res.headers().contains_key(header::SET_COOKIE));
+    let calls = h.forecast_calls();
+
+    // The choice is remembered in a cookie for the following pages
+    let (status, res) = h.get("/weather?city=London&units=imperial", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("54.5°F"));
+    assert!(!res.body().contains("12.5°C"));
+    let units = cookie(&res, "forecast_units");
+    assert_eq!(units, "forecast_units=imperial");
+
+    // Forecasts are cached in metric and converted per request
+    assert_eq!(h.forecast_calls(), calls);
+
+    let cookie = [(header::COOKIE, units.as_str())];
+    let (status, res) = h.get_with("/weather?city=London&view=daily", &cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("59.9°F"));
+
+    let (status, res) = h.get_with("/weather/chart.svg?city=London", &cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains(">max 54.5°F</text>"));
+    assert!(res.body().contains("<title>0.02 in</title>"));
+
+    // An explicit parameter wins over the cookie
+    let (status, res) = h
+        .get_with("/weather?city=London&units=metric", &cookie)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("12.5°C"));
+
+    let uri = "/api/v1/weather?city=London&variables=temperature&units=imperial";
+    let (status, res) = h.get(uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!res.headers().contains_key(header::SET_COOKIE));
+    let body: Value = serde_json::from_str(res.body()).expect("parse json body");
+    assert_eq!(body["units"], "imperial");
+    assert_eq!(body["hourly"]["temperature_2m"][0], 54.5);
+
+    // Switching the units or the view keeps the selected variables
+    let uri = "/weather?city=London&variables=temperature,weather_code";
+    let (status, res) = h.get(uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let links = "/weather?city=London&amp;variables=temperature%2Cweather_code";
+    assert!(res.body().contains(&format!("{links}&units=imperial\"")));
+    assert!(res.body().contains(&format!("{links}&view=daily\"")));
+
+    let (status, res) = h.get(&format!("{uri}&view=daily"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res
+        .body()
+        .contains(&format!("{links}&view=daily&units=imperial\"")));
+    assert!(res.body().contains(&format!("\"{links}\">Hourly forecast")));
+
+    let (status, _) = h.get("/weather?city=London&units=kelvin", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn weather_local_time() {
+    let Some(h) = Harness::start().await else {
+        return;
+    };
+
+    let (status, res) = h.get("/weather?city=London", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(h.forecast_param("timezone"), "Europe/London");
+    assert!(res
+        .body()
+        .contains("Times are in Europe/London (BST, UTC+01:00)."));
+    assert!(res
+        .body()
+        .contains("<th colspan=\"10\">Sunday, 1 October 2023</th>"));
+    assert!(res.body().contains("<td>01:00</td>"));
+    assert!(!res.body().contains("<strong>now</strong>"));
+
+    let (status, res) = h.get("/weather?city=London&view=daily", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res.body().contains("<td>Sunday, 1 October 2023</td>"));
+    assert!(res.body().contains("<td>06:01</td>"));
+
+    let uri = "/api/v1/weather?city=London&variables=temperature";
+    let (status, res) = h.get(uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: Value = serde_json::from_str(res.body()).expect("parse json body");
+    assert_eq!(body["timezone"], "Europe/London");
+    assert_eq!(body["utc_offset_seconds"], 3600);
+    assert_eq!(body["hourly"]["time"][1], "2023-10-01T01:00");
+
+    // Coordinates have no stored time zone, so the upstream picks it, and
+    // the forecast isn't shared with the place nearby
+    let calls = h.forecast_calls();
+    let (status, res) = h.get("/weather?lat=51.51&lng=-0.13", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(h.forecast_param("timezone"), "auto");
+    assert!(res.body().contains("Times are in UTC+00:00."));
+    assert_eq!(h.forecast_calls(), calls + 1);
+
+    // The current hour at the location is marked
+    h.stub.current_hours.store(true, Ordering::SeqCst);
+    let (status, res) = h
+        .get("/weather?city=London&variables=cloud_cover", None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(res.body().matches("<strong>now</strong>").count(), 1);
+    assert!(res.body().contains("<tr class=\"now\">"));
+
+    h.finish().await;
+}
+
+#[tokio::test]
+async fn weather_current_conditions() {
+    let Some(h) = Harness::start_with(|config| config.cache.current_ttl_secs = 0).await else {
+        return;
+    };
+
+    let (status, res) = h.get("/weather?city=London", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let body = res.body();
+    assert!(body.contains("<h2>Current conditions</h2>"));
+    assert!(body.contains("🌧️</span> 13°C, Slight rain"));
+    assert!(body.contains("<li>Feels like 11.5°C</li>"));
+    assert!(body.contains("<li>Wind 14.4 km/h SW</li>"));
+    assert!(body.contains("<li>Humidity 82%</li>"));
+    assert!(body.contains("Observed at 00:45."));
+    assert_eq!(h.stub.current_calls.load(Ordering::SeqCst), 1);
+    assert_eq!(h.forecast_calls(), 1);
+
+    // The current conditions expire sooner than the forecast
+    let uri = "/api/v1/weather?city=London&units=imperial";
+    let (status, res) = h.get(uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: Value = serde_json::from_str(res.body()).expect("parse json body");
+    assert_eq!(body["current"]["temperature_2m"], 55.4);
+    assert_eq!(body["current"]["time"], "2023-10-01T00:45");
+    assert_eq!(h.stub.current_calls.load(Ordering::SeqCst), 2);
+    assert_eq!(h.forecast_calls(), 1);
+
+    let (status, res) = h.get("/weather?city=London&view=daily", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!res.body().contains("Current conditions"));
+    assert_eq!(h.stub.current_calls.load(Ordering::SeqCst), 2);
+
+    // The page does without them when they can't be had
+    h.stub.current_fails.store(true, Ordering::SeqCst);
+    let (status, res) = h.get("/weather?city=London", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(res
+        .body()
+        .contains("Observed at 00:45, the forecast service is unavailable."));
+
+    let (status, res) = h.get("/weather?lat=10&lng=20", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!res.body().contains("Current conditions"));
+
+    h.finish().await;
+}
@@ -0,0 +1,172 @@
+//! Metric and imperial units.
+//!
+//! Forecasts are fetched and cached in metric units and converted when
+//! they're shown, so both unit systems share the cached forecasts.
+
+use {
+    crate::provider::{Current, Daily, Hourly, WeatherResponse},
+    axum::http::{header, HeaderMap, HeaderValue},
+    cookie::{time, Cookie, SameSite},
+    serde::{Deserialize, Serialize},
+};
+
+const UNITS_COOKIE: &str = "forecast_units";
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    /// °C, km/h and mm.
+    #[default]
+    Metric,
+    /// °F, mph and inches.
+    Imperial,
+}
+
+impl Units {
+    pub const ALL: [Self; 2] = [Self::Metric, Self::Imperial];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Metric => "metric",
+            Self::Imperial => "imperial",
+        }
+    }
+
+    pub fn temperature(self) -> &'static str {
+        match self {
+            Self::Metric => "°C",
+            Self::Imperial => "°F",
+        }
+    }
+
+    pub fn wind_speed(self) -> &'static str {
+        match self {
+            Self::Metric => "km/h",
+            Self::Imperial => "mph",
+        }
+    }
+
+    pub fn precipitation(self) -> &'static str {
+        match self {
+            Self::Metric => "mm",
+            Self::Imperial => "in",
+        }
+    }
+
+    /// Converts a temperature from °C.
+    pub fn convert_temperature(self, celsius: f64) -> f64 {
+        match self {
+            Self::Metric => celsius,
+            Self::Imperial => round(celsius * 9. / 5. + 32., 1),
+        }
+    }
+
+    /// Converts a wind speed from km/h.
+    pub fn convert_wind_speed(self, kmh: f64) -> f64 {
+        match self {
+            Self::Metric => kmh,
+            Self::Imperial => round(kmh / 1.609_344, 1),
+        }
+    }
+
+    /// Converts an amount of precipitation from mm.
+    pub fn convert_precipitation(self, mm: f64) -> f64 {
+        match self {
+            Self::Metric => mm,
+            Self::Imperial => round(mm / 25.4, 2),
+        }
+    }
+
+    /// Converts a forecast from metric units.
+    pub fn convert(self, response: &WeatherResponse) -> WeatherResponse {
+        let series = |series: &[Option<f64>], convert: fn(Self, f64) -> f64| {
+            let convert = |value: &Option<f64>| value.map(|value| convert(self, value));
+            series.iter().map(convert).collect()
+        };
+
+        let hourly = &response.hourly;
+        let daily = response.daily.as_ref().map(|daily| Daily {
+            temperature_2m_min: series(&daily.temperature_2m_min, Self::convert_temperature),
+            temperature_2m_max: series(&daily.temperature_2m_max, Self::convert_temperature),
+            precipitation_sum: series(&daily.precipitation_sum, Self::convert_precipitation),
+            ..daily.clone()
+        });
+
+        let current = response.current.as_ref().map(|current| Current {
+            temperature_2m: current.temperature_2m.map(|t| self.convert_temperature(t)),
+            apparent_temperature: current
+                .apparent_temperature
+                .map(|t| self.convert_temperature(t)),
+            wind_speed_10m: current
+                .wind_speed_10m
+                .map(|speed| self.convert_wind_speed(speed)),
+            ..current.clone()
+        });
+
+        WeatherResponse {
+            utc_offset_seconds: response.utc_offset_seconds,
+            timezone: response.timezone.clone(),
+            timezone_abbreviation: response.timezone_abbreviation.clone(),
+            hourly: Hourly {
+                temperature_2m: series(&hourly.temperature_2m, Self::convert_temperature),
+                apparent_temperature: series(
+                    &hourly.apparent_temperature,
+                    Self::convert_temperature,
+                ),
+                precipitation: series(&hourly.precipitation, Self::convert_precipitation),
+                wind_speed_10m: series(&hourly.wind_speed_10m, Self::convert_wind_speed),
+                ..hourly.clone()
+            },
+            daily,
+            current,
+        }
+    }
+
+    /// The units asked for in the query, or else the ones remembered in
+    /// the cookie.
+    pub fn choose(query: Option<Self>, headers: &HeaderMap) -> Self {
+        query
+            .or_else(|| Self::from_cookie(headers))
+            .unwrap_or_default()
+    }
+
+    fn from_cookie(headers: &HeaderMap) -> Option<Self> {
+        let values = headers.get_all(header::COOKIE).iter();
+        let cookies = values
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(Cookie::split_parse)
+            .flatten();
+
+        let cookie = cookies
+            .filter(|cookie| cookie.name() == UNITS_COOKIE)
+            .last()?;
+        Self::ALL
+            .into_iter()
+            .find(|units| units.name() == cookie.value())
+    }
+
+    /// The cookie that remembers the units for a year.
+    pub fn cookie(self, secure: bool) -> HeaderValue {
+        let cookie = Cookie::build((UNITS_COOKIE, self.name()))
+            .path("/")
+            .same_site(SameSite::Lax)
+            .secure(secure)
+            .max_age(time::Duration::days(365));
+
+        HeaderValue::from_str(&cookie.to_string()).expect("valid header value")
+    }
+}
+
+/// Formats a value along with its unit symbol.
+pub fn with_unit(value: f64, unit: &str) -> String {
+    if unit.starts_with(['°', '%']) {
+        format!("{value}{unit}")
+    } else {
+        format!("{value} {unit}")
+    }
+}
+
+fn round(value: f64, decimals: i32) -> f64 {
+    let scale = 10_f64.powi(decimals);
+    (value * scale).round() / scale
+}
@@ -0,0 +1,286 @@
+use {
+    crate::{api_key::ApiKey, error::Error, session, App},
+    argon2::{
+        password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString},
+        Argon2, PasswordVerifier,
+    },
+    axum::{
+        extract::FromRequestParts,
+        headers::{
+            authorization::{Basic, Bearer},
+            Authorization,
+        },
+        http::request::Parts,
+        TypedHeader,
+    },
+    clap::ValueEnum,
+    serde::Serialize,
+    sqlx::{FromRow, PgPool},
+    std::{fmt, marker::PhantomData, sync::OnceLock},
+};
+
+const MIN_PASSWORD_LEN: usize = 8;
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, sqlx::Type, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Operator,
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Viewer => write!(f, "viewer"),
+            Self::Operator => write!(f, "operator"),
+            Self::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// An authenticated user.
+#[derive(Clone, FromRow, Serialize)]
+pub struct User {
+    pub id: i32,
+    pub name: String,
+    pub role: Role,
+    /// The CSRF token of the session the user signed in with, if any.
+    #[serde(skip)]
+    pub csrf: Option<String>,
+}
+
+impl User {
+    /// Checks the credentials of an enabled user.
+    pub async fn authenticate(
+        pool: &PgPool,
+        name: &str,
+        password: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let row: Option<(i32, String, Role, String)> = sqlx::query_as(
+            "SELECT id, name, role, password_hash FROM users WHERE name = $1 AND NOT disabled",
+        )
+        .bind(name)
+        .fetch_optional(pool)
+        .await?;
+
+        // Verify against a dummy hash for unknown users, so the response time
+        // doesn't reveal which names exist
+        let hash = match &row {
+            Some((.., hash)) => hash.clone(),
+            None => dummy_hash().to_owned(),
+        };
+
+        let valid = verify_password(password.to_owned(), hash).await;
+        Ok(row.filter(|_| valid).map(|(id, name, role, _)| Self {
+            id,
+            name,
+            role,
+            csrf: None,
+        }))
+    }
+}
+
+#[async_trait::async_trait]
+impl FromRequestParts<App> for User {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, app: &App) -> Result<Self, Self::Rejection> {
+        if let Some(user) = session::user(app, &parts.headers).await? {
+            return Ok(user);
+        }
+
+        let auth: TypedHeader<Authorization<Basic>> =
+            match TypedHeader::from_request_parts(parts, app).await {
+                Ok(auth) => auth,
+                Err(_) => match session::login_redirect(parts) {
+                    Some(login) => return Err(Error::LoginRequired(login)),
+                    None => return Err(Error::Unauthorized),
+                },
+            };
+
+        Self::authenticate(&app.pool, auth.username(), auth.password())
+            .await?
+            .ok_or(Error::Unauthorized)
+    }
+}
+
+/// The least role a route requires, as the parameter of [`RequireRole`].
+pub trait MinRole {
+    const ROLE: Role;
+}
+
+pub struct Viewer;
+
+impl MinRole for Viewer {
+    const ROLE: Role = Role::Viewer;
+}
+
+pub struct Admin;
+
+impl MinRole for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+/// A user with at least the role `R`, such as `RequireRole<Admin>`.
+///
+/// Rejects known callers without the role with 403, unlike [`User`] which
+/// only rejects unknown ones with 401.
+pub struct RequireRole<R>(pub User, pub PhantomData<R>);
+
+#[async_trait::async_trait]
+impl<R> FromRequestParts<App> for RequireRole<R>
+where
+    R: MinRole,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, app: &App) -> Result<Self, Self::Rejection> {
+        // API keys identify a caller too, but they don't have a role
+        let bearer = TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, app).await;
+        if let Ok(TypedHeader(Authorization(bearer))) = bearer {
+            return match ApiKey::authenticate(&app.pool, bearer.token()).await? {
+                Some(_) => Err(Error::Forbidden("API keys can't use this route".to_owned())),
+                None => Err(Error::Unauthorized),
+            };
+        }
+
+        let user = User::from_request_parts(parts, app).await?;
+        if user.role < R::ROLE {
+            let message = format!("this route requires the {} role", R::ROLE);
+            return Err(Error::Forbidden(message));
+        }
+
+        Ok(Self(user, PhantomData))
+    }
+}
+
+fn dummy_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| hash_password("dummy password").expect("hash dummy password"))
+}
+
+fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+/// Verifies a password on the blocking pool, since hashing is slow.
+async fn verify_password(password: String, hash: String) -> bool {
+    let verify = move || {
+        let Ok(hash) = PasswordHash::new(&hash) else {
+            return false;
+        };
+
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    };
+
+    tokio::task::spawn_blocking(verify).await.unwrap_or(false)
+}
+
+/// A user as listed by the `user list` command.
+#[derive(FromRow)]
+pub struct Account {
+    pub name: String,
+    pub role: Role,
+    pub disabled: bool,
+}
+
+pub async fn create(pool: &PgPool, name: &str, password: &str, role: Role) -> Result<(), Failure> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN || name.contains(':') {
+        return Err(Failure::Invalid(format!(
+            "the name must be 1 to {MAX_NAME_LEN} bytes long and must not contain ':'",
+        )));
+    }
+
+    let hash = checked_hash(password)?;
+    let res = sqlx::query("INSERT INTO users (name, password_hash, role) VALUES ($1, $2, $3)")
+        .bind(name)
+        .bind(hash)
+        .bind(role)
+        .execute(pool)
+        .await;
+
+    match res {
+        Ok(_) => Ok(()),
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+            Err(Failure::Exists(name.to_owned()))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub async fn set_password(pool: &PgPool, name: &str, password: &str) -> Result<(), Failure> {
+    let hash = checked_hash(password)?;
+    let res = sqlx::query("UPDATE users SET password_hash = $2 WHERE name = $1")
+        .bind(name)
+        .bind(hash)
+        .execute(pool)
+        .await?;
+
+    match res.rows_affected() {
+        0 => Err(Failure::NotFound(name.to_owned())),
+        _ => Ok(()),
+    }
+}
+
+pub async fn set_disabled(pool: &PgPool, name: &str, disabled: bool) -> Result<(), Failure> {
+    let res = sqlx::query("UPDATE users SET disabled = $2 WHERE name = $1")
+        .bind(name)
+        .bind(disabled)
+        .execute(pool)
+        .await?;
+
+    match res.rows_affected() {
+        0 => Err(Failure::NotFound(name.to_owned())),
+        _ => Ok(()),
+    }
+}
+
+pub async fn list(pool: &PgPool) -> Result<Vec<Account>, Failure> {
+    let accounts = sqlx::query_as("SELECT name, role, disabled FROM users ORDER BY name")
+        .fetch_all(pool)
+        .await?;
+
+    Ok(accounts)
+}
+
+fn checked_hash(password: &str) -> Result<String, Failure> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(Failure::Invalid(format!(
+            "the password must be at least {MIN_PASSWORD_LEN} characters long",
+        )));
+    }
+
+    hash_password(password).map_err(|err| Failure::Invalid(err.to_string()))
+}
+
+/// A failed user management command.
+pub enum Failure {
+    Database(sqlx::Error),
+    Invalid(String),
+    Exists(String),
+    NotFound(String),
+}
+
+impl From<sqlx::Error> for Failure {
+    fn from(v: sqlx::Error) -> Self {
+        Self::Database(v)
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Database(err) => write!(f, "database error: {err}"),
+            Self::Invalid(message) => write!(f, "{message}"),
+            Self::Exists(name) => write!(f, "the user {name:?} already exists"),
+            Self::NotFound(name) => write!(f, "the user {name:?} doesn't exist"),
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use {crate::units::Units, std::fmt};
+
+/// An hourly forecast variable.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Variable {
+    Temperature,
+    ApparentTemperature,
+    Precipitation,
+    PrecipitationProbability,
+    RelativeHumidity,
+    CloudCover,
+    WindSpeed,
+    WindDirection,
+    WeatherCode,
+}
+
+impl Variable {
+    pub const ALL: [Self; 9] = [
+        Self::Temperature,
+        Self::ApparentTemperature,
+        Self::Precipitation,
+        Self::PrecipitationProbability,
+        Self::RelativeHumidity,
+        Self::CloudCover,
+        Self::WindSpeed,
+        Self::WindDirection,
+        Self::WeatherCode,
+    ];
+
+    /// The name used in the `variables` query parameter.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Temperature => "temperature",
+            Self::ApparentTemperature => "apparent_temperature",
+            Self::Precipitation => "precipitation",
+            Self::PrecipitationProbability => "precipitation_probability",
+            Self::RelativeHumidity => "relative_humidity",
+            Self::CloudCover => "cloud_cover",
+            Self::WindSpeed => "wind_speed",
+            Self::WindDirection => "wind_direction",
+            Self::WeatherCode => "weather_code",
+        }
+    }
+
+    /// The name of the variable in the Open-Meteo API.
+    pub fn api_name(self) -> &'static str {
+        match self {
+            Self::Temperature => "temperature_2m",
+            Self::ApparentTemperature => "apparent_temperature",
+            Self::Precipitation => "precipitation",
+            Self::PrecipitationProbability => "precipitation_probability",
+            Self::RelativeHumidity => "relative_humidity_2m",
+            Self::CloudCover => "cloud_cover",
+            Self::WindSpeed => "wind_speed_10m",
+            Self::WindDirection => "wind_direction_10m",
+            Self::WeatherCode => "weather_code",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Temperature => "Temperature",
+            Self::ApparentTemperature => "Feels like",
+            Self::Precipitation => "Precipitation",
+            Self::PrecipitationProbability => "Precipitation chance",
+            Self::RelativeHumidity => "Humidity",
+            Self::CloudCover => "Cloud cover",
+            Self::WindSpeed => "Wind speed",
+            Self::WindDirection => "Wind direction",
+            Self::WeatherCode => "Conditions",
+        }
+    }
+
+    /// The symbol of the unit of the values, empty for weather codes.
+    pub fn unit(self, units: Units) -> &'static str {
+        match self {
+            Self::Temperature | Self::ApparentTemperature => units.temperature(),
+            Self::Precipitation => units.precipitation(),
+            Self::PrecipitationProbability | Self::RelativeHumidity | Self::CloudCover => "%",
+            Self::WindSpeed => units.wind_speed(),
+            Self::WindDirection => "°",
+            Self::WeatherCode => "",
+        }
+    }
+
+    /// Parses a comma separated list of variable names, skipping duplicates.
+    pub fn parse_list(list: &str) -> Result<Vec<Self>, UnknownVariable> {
+        let mut variables = vec![];
+        for name in list
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            let variable = Self::ALL
+                .into_iter()
+                .find(|variable| variable.name() == name)
+                .ok_or_else(|| UnknownVariable(name.to_owned()))?;
+
+            if !variables.contains(&variable) {
+                variables.push(variable);
+            }
+        }
+
+        Ok(variables)
+    }
+}
+
+pub struct UnknownVariable(String);
+
+impl fmt::Display for UnknownVariable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Self(name) = self;
+        write!(f, "unknown variable {name:?}, expected one of: ")?;
+        for (n, variable) in Variable::ALL.into_iter().enumerate() {
+            let sep = if n == 0 { "" } else { ", " };
+            write!(f, "{sep}{}", variable.name())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Describes a [WMO weather interpretation code](https://open-meteo.com/en/docs).
+pub fn describe_weather_code(code: u8) -> &'static str {
+    match code {
+        0 => "Clear sky",
+        1 => "Mainly clear",
+        2 => "Partly cloudy",
+        3 => "Overcast",
+        45 => "Fog",
+        48 => "Depositing rime fog",
+        51 => "Light drizzle",
+        53 => "Moderate drizzle",
+        55 => "Dense drizzle",
+        56 => "Light freezing drizzle",
+        57 => "Dense freezing drizzle",
+        61 => "Slight rain",
+        63 => "Moderate rain",
+        65 => "Heavy rain",
+        66 => "Light freezing rain",
+        67 => "Heavy freezing rain",
+        71 => "Slight snow fall",
+        73 => "Moderate snow fall",
+        75 => "Heavy snow fall",
+        77 => "Snow grains",
+        80 => "Slight rain showers",
+        81 => "Moderate rain showers",
+        82 => "Violent rain showers",
+        85 => "Slight snow showers",
+        86 => "Heavy snow showers",
+        95 => "Thunderstorm",
+        96 => "Thunderstorm with slight hail",
+        99 => "Thunderstorm with heavy hail",
+        _ => "Unknown",
+    }
+}
+
+/// An icon of a [WMO weather interpretation code](https://open-meteo.com/en/docs).
+pub fn weather_code_icon(code: u8) -> &'static str {
+    match code {
+        0 => "☀️",
+        1 => "🌤️",
+        2 => "⛅",
+        3 => "☁️",
+        45 | 48 => "🌫️",
+        51..=57 => "🌦️",
+        61..=67 | 80..=82 => "🌧️",
+        71..=77 | 85 | 86 => "🌨️",
+        95..=99 => "⛈️",
+        _ => "❔",
+    }
+}
+
+/// Names the compass point closest to a direction in degrees.
+pub fn compass_point(degrees: f64) -> &'static str {
+    const POINTS: [&str; 8] = ["N", "NE", "E", "SE", "S", "SW", "W", "NW"];
+
+    let sector = (degrees.rem_euclid(360.) / 45.).round() as usize % POINTS.len();
+    POINTS[sector]
+}